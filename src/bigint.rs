@@ -0,0 +1,190 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+/// Arbitrary-precision unsigned integer, for values that do not fit in a
+/// `u64`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub(crate) struct BigUint {
+    /// Little-endian base 2^32 digits, without trailing zeros.
+    limbs: Vec<u32>,
+}
+
+impl BigUint {
+    /// Sets `self` to `self * factor + addend`.
+    fn mul_add_small(&mut self, factor: u32, addend: u32) {
+        let mut carry = addend as u64;
+        for limb in self.limbs.iter_mut() {
+            let v = *limb as u64 * factor as u64 + carry;
+            *limb = v as u32;
+            carry = v >> 32;
+        }
+        if carry > 0 {
+            self.limbs.push(carry as u32);
+        }
+        self.trim();
+    }
+
+    /// Sets `self` to `self / divisor` and returns the remainder.
+    fn div_rem_small(&mut self, divisor: u32) -> u32 {
+        let mut remainder = 0u64;
+        for limb in self.limbs.iter_mut().rev() {
+            let v = (remainder << 32) | *limb as u64;
+            *limb = (v / divisor as u64) as u32;
+            remainder = v % divisor as u64;
+        }
+        self.trim();
+        remainder as u32
+    }
+
+    pub(crate) fn push_nibble(&mut self, nibble: u32) {
+        self.mul_add_small(16, nibble);
+    }
+
+    pub(crate) fn nibble(&self, i: u32) -> u32 {
+        let limb = self.limbs.get(i as usize / 8).copied().unwrap_or(0);
+        (limb >> (4 * (i % 8))) & 0b1111
+    }
+
+    pub(crate) fn bits(&self) -> u32 {
+        match self.limbs.last() {
+            Some(last) => 32 * self.limbs.len() as u32 - last.leading_zeros(),
+            None => 0,
+        }
+    }
+
+    pub(crate) fn to_u64(&self) -> Option<u64> {
+        match self.limbs[..] {
+            [] => Some(0),
+            [low] => Some(low as u64),
+            [low, high] => Some(((high as u64) << 32) | low as u64),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn add(&self, rhs: &Self) -> Self {
+        let mut limbs = Vec::new();
+        let mut carry = 0u64;
+        for i in 0..self.limbs.len().max(rhs.limbs.len()) {
+            let v = self.limbs.get(i).copied().unwrap_or(0) as u64
+                + rhs.limbs.get(i).copied().unwrap_or(0) as u64
+                + carry;
+            limbs.push(v as u32);
+            carry = v >> 32;
+        }
+        limbs.push(carry as u32);
+
+        let mut sum = Self { limbs };
+        sum.trim();
+        sum
+    }
+
+    pub(crate) fn mul(&self, rhs: &Self) -> Self {
+        let mut limbs = vec![0u32; self.limbs.len() + rhs.limbs.len()];
+        for (i, a) in self.limbs.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, b) in rhs.limbs.iter().enumerate() {
+                let v = limbs[i + j] as u64 + *a as u64 * *b as u64 + carry;
+                limbs[i + j] = v as u32;
+                carry = v >> 32;
+            }
+            limbs[i + rhs.limbs.len()] = carry as u32;
+        }
+
+        let mut product = Self { limbs };
+        product.trim();
+        product
+    }
+
+    fn trim(&mut self) {
+        while self.limbs.last() == Some(&0) {
+            self.limbs.pop();
+        }
+    }
+}
+
+impl From<u64> for BigUint {
+    fn from(v: u64) -> Self {
+        let mut big = Self {
+            limbs: vec![v as u32, (v >> 32) as u32],
+        };
+        big.trim();
+        big
+    }
+}
+
+impl Ord for BigUint {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.limbs
+            .len()
+            .cmp(&other.limbs.len())
+            .then_with(|| self.limbs.iter().rev().cmp(other.limbs.iter().rev()))
+    }
+}
+
+impl PartialOrd for BigUint {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for BigUint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const CHUNK: u32 = 1_000_000_000;
+        let mut rest = self.clone();
+        let mut chunks = vec![rest.div_rem_small(CHUNK)];
+        while !rest.limbs.is_empty() {
+            chunks.push(rest.div_rem_small(CHUNK));
+        }
+
+        write!(f, "{}", chunks.pop().unwrap())?;
+        for chunk in chunks.iter().rev() {
+            write!(f, "{:09}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for BigUint {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, ()> {
+        if s.is_empty() {
+            return Err(());
+        }
+        let mut big = Self::default();
+        for c in s.chars() {
+            big.mul_add_small(10, c.to_digit(10).ok_or(())?);
+        }
+        Ok(big)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn big_uint() {
+        let mut big = BigUint::default();
+        assert_eq!(big.to_string(), "0");
+        for _ in 0..20 {
+            big.push_nibble(0xF);
+        }
+        assert_eq!(big.to_string(), "1208925819614629174706175");
+        assert_eq!(big.bits(), 80);
+        assert_eq!(big.to_u64(), None);
+        assert_eq!("1208925819614629174706175".parse(), Ok(big.clone()));
+        assert_eq!(
+            big.add(&BigUint::from(1)).to_string(),
+            "1208925819614629174706176"
+        );
+        assert!(BigUint::from(u64::MAX) < big);
+        assert_eq!(
+            "1000000000000000000"
+                .parse::<BigUint>()
+                .unwrap()
+                .to_string(),
+            "1000000000000000000"
+        );
+    }
+}
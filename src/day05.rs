@@ -1,4 +1,6 @@
-use std::collections::HashMap;
+use crate::map::{check_map_size, MapTooLarge};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::str::FromStr;
 
@@ -25,24 +27,74 @@ impl Line {
         self.p1.x == self.p2.x || self.p1.y == self.p2.y
     }
 
-    fn points_between(&self) -> Vec<Point> {
+    /// The step between consecutive lattice points of the line, and how many
+    /// steps it spans. Lines that are a single point step horizontally.
+    fn step(&self) -> (Point, i64) {
         let diff_x = self.p2.x - self.p1.x;
         let diff_y = self.p2.y - self.p1.y;
-        let dx = diff_x.signum();
-        let dy = diff_y.signum();
-        let steps = diff_x.abs().max(diff_y.abs());
-
-        let mut vec = Vec::new();
-        for i in 0..=steps {
-            vec.push(Point {
-                x: self.p1.x + i * dx,
-                y: self.p1.y + i * dy,
-            });
+        let steps = gcd(diff_x, diff_y);
+
+        if steps == 0 {
+            (Point { x: 1, y: 0 }, 0)
+        } else {
+            let step = Point {
+                x: diff_x / steps,
+                y: diff_y / steps,
+            };
+            (step, steps)
+        }
+    }
+
+    /// The lattice points exactly on the line.
+    fn points(&self) -> Vec<Point> {
+        let (step, steps) = self.step();
+        (0..=steps)
+            .map(|i| Point {
+                x: self.p1.x + i * step.x,
+                y: self.p1.y + i * step.y,
+            })
+            .collect()
+    }
+
+    /// The cells Bresenham's algorithm draws for the line, from `p1` to `p2`.
+    fn cells(&self) -> Vec<Point> {
+        let diff_x = (self.p2.x - self.p1.x).abs();
+        let diff_y = -(self.p2.y - self.p1.y).abs();
+        let step_x = if self.p1.x < self.p2.x { 1 } else { -1 };
+        let step_y = if self.p1.y < self.p2.y { 1 } else { -1 };
+
+        let mut cells = Vec::new();
+        let mut point = self.p1;
+        let mut error = diff_x + diff_y;
+        loop {
+            cells.push(point);
+            if point == self.p2 {
+                return cells;
+            }
+
+            let error2 = 2 * error;
+            if error2 >= diff_y {
+                error += diff_y;
+                point.x += step_x;
+            }
+            if error2 <= diff_x {
+                error += diff_x;
+                point.y += step_y;
+            }
         }
-        vec
     }
 }
 
+/// Which points a line covers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Coverage {
+    /// The lattice points exactly on the line.
+    Exact,
+    /// The cells Bresenham's algorithm draws for the line, which every line
+    /// has one of per column or row.
+    Bresenham,
+}
+
 impl FromStr for Line {
     type Err = ();
     fn from_str(s: &str) -> Result<Self, ()> {
@@ -54,10 +106,20 @@ impl FromStr for Line {
     }
 }
 
-#[derive(Clone, Copy, Eq, PartialEq, Hash)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
 struct Point {
-    x: i16,
-    y: i16,
+    x: i64,
+    y: i64,
+}
+
+impl Point {
+    fn dot(&self, other: &Point) -> i128 {
+        self.x as i128 * other.x as i128 + self.y as i128 * other.y as i128
+    }
+
+    fn cross(&self, other: &Point) -> i128 {
+        self.x as i128 * other.y as i128 - self.y as i128 * other.x as i128
+    }
 }
 
 impl FromStr for Point {
@@ -71,37 +133,354 @@ impl FromStr for Point {
     }
 }
 
+/// Prints how many points the lines in the file at `path` cover at least
+/// `threshold` times. `coverage` is `exact` (the default) or `bresenham`.
+pub fn overlaps_command(path: &str, threshold: &str, coverage: Option<&str>) -> Result<(), String> {
+    let content =
+        fs::read_to_string(path).map_err(|e| format!("Unable to read vent lines: {}", e))?;
+    let threshold = threshold
+        .parse()
+        .map_err(|_| format!("Invalid threshold: {}", threshold))?;
+    let coverage = match coverage {
+        Some("exact") | None => Coverage::Exact,
+        Some("bresenham") => Coverage::Bresenham,
+        Some(other) => return Err(format!("Unknown coverage: {}", other)),
+    };
+    let lines = parse_lines(&content);
+
+    let count = match coverage {
+        Coverage::Exact => count_overlaps(&lines, threshold),
+        Coverage::Bresenham => counts(&lines, coverage)
+            .into_values()
+            .filter(|v| *v >= threshold)
+            .count(),
+    };
+    println!("{}", count);
+    Ok(())
+}
+
+/// Prints the coverage of the lines in the file at `path` like the puzzle
+/// does, or writes it as a PGM heatmap to `image_path`.
+pub fn map_command(path: &str, image_path: Option<&str>) -> Result<(), String> {
+    let content =
+        fs::read_to_string(path).map_err(|e| format!("Unable to read vent lines: {}", e))?;
+    let counts = counts(&parse_lines(&content), Coverage::Exact);
+
+    match image_path {
+        Some(image_path) => {
+            let image = render_pgm(&counts).map_err(|e| e.to_string())?;
+            fs::write(image_path, image).map_err(|e| format!("Unable to write image: {}", e))?;
+        }
+        None => print!("{}", render(&counts).map_err(|e| e.to_string())?),
+    }
+    Ok(())
+}
+
+/// Prints how many points the lines in the file at `path` cover how often,
+/// and the `top` most covered points.
+pub fn stats_command(path: &str, top: Option<&str>) -> Result<(), String> {
+    let content =
+        fs::read_to_string(path).map_err(|e| format!("Unable to read vent lines: {}", e))?;
+    let top = match top {
+        Some(n) => n
+            .parse()
+            .map_err(|_| format!("Invalid number of points: {}", n))?,
+        None => 5,
+    };
+    let counts = counts(&parse_lines(&content), Coverage::Exact);
+
+    for (coverage, points) in histogram(&counts) {
+        println!("Covered {} times: {} points", coverage, points);
+    }
+    for (point, coverage) in hottest(&counts, top) {
+        println!("{},{}: {}", point.x, point.y, coverage);
+    }
+    Ok(())
+}
+
+/// Prints how many points within the rectangle with corners `corner1` and
+/// `corner2` (as `x,y`) the lines in the file at `path` cover at least
+/// `threshold` times.
+pub fn region_command(
+    path: &str,
+    corner1: &str,
+    corner2: &str,
+    threshold: &str,
+) -> Result<(), String> {
+    let content =
+        fs::read_to_string(path).map_err(|e| format!("Unable to read vent lines: {}", e))?;
+    let corner = |s: &str| s.parse().map_err(|_| format!("Invalid corner: {}", s));
+    let (corner1, corner2) = (corner(corner1)?, corner(corner2)?);
+    let threshold = threshold
+        .parse()
+        .map_err(|_| format!("Invalid threshold: {}", threshold))?;
+    let counts = counts(&parse_lines(&content), Coverage::Exact);
+
+    println!("{}", overlaps_within(&counts, corner1, corner2, threshold));
+    Ok(())
+}
+
 fn problem1(lines: &[Line]) -> usize {
+    count_overlaps(lines.iter().filter(|l| l.is_orthogonal()), 2)
+}
+
+fn problem2(lines: &[Line]) -> usize {
+    count_overlaps(lines, 2)
+}
+
+/// How many lines cover each point.
+fn counts(lines: &[Line], coverage: Coverage) -> HashMap<Point, usize> {
     let mut counts = HashMap::new();
     for line in lines {
-        if !line.is_orthogonal() {
-            continue;
+        let points = match coverage {
+            Coverage::Exact => line.points(),
+            Coverage::Bresenham => line.cells(),
+        };
+        for point in points {
+            *counts.entry(point).or_insert(0) += 1;
         }
+    }
+    counts
+}
 
-        for point in line.points_between().into_iter() {
-            let entry = counts.entry(point).or_insert(0);
-            *entry += 1;
+/// How many points within the rectangle with corners `corner1` and `corner2`
+/// are covered at least `threshold` times.
+fn overlaps_within(
+    counts: &HashMap<Point, usize>,
+    corner1: Point,
+    corner2: Point,
+    threshold: usize,
+) -> usize {
+    let xs = corner1.x.min(corner2.x)..=corner1.x.max(corner2.x);
+    let ys = corner1.y.min(corner2.y)..=corner1.y.max(corner2.y);
+    counts
+        .iter()
+        .filter(|(p, c)| **c >= threshold && xs.contains(&p.x) && ys.contains(&p.y))
+        .count()
+}
+
+/// The `n` most covered points with their coverage, most covered first and
+/// in reading order among equals.
+fn hottest(counts: &HashMap<Point, usize>, n: usize) -> Vec<(Point, usize)> {
+    let mut points = counts.iter().map(|(p, c)| (*p, *c)).collect::<Vec<_>>();
+    points.sort_unstable_by_key(|(p, c)| (Reverse(*c), p.y, p.x));
+    points.truncate(n);
+    points
+}
+
+/// How many points are covered each number of times, for points covered at
+/// all.
+fn histogram(counts: &HashMap<Point, usize>) -> BTreeMap<usize, usize> {
+    let mut histogram = BTreeMap::new();
+    for coverage in counts.values() {
+        *histogram.entry(*coverage).or_insert(0) += 1;
+    }
+    histogram
+}
+
+/// The corners of the covered points, if the area between them can be
+/// mapped.
+fn map_bounds(counts: &HashMap<Point, usize>) -> Result<(Point, Point), MapTooLarge> {
+    let (min, max) = bounds(counts);
+    check_map_size((min.x, min.y), (max.x, max.y))?;
+    Ok((min, max))
+}
+
+/// The smallest and largest corners of the covered points.
+fn bounds(counts: &HashMap<Point, usize>) -> (Point, Point) {
+    let min = Point {
+        x: counts.keys().map(|p| p.x).min().unwrap_or(0),
+        y: counts.keys().map(|p| p.y).min().unwrap_or(0),
+    };
+    let max = Point {
+        x: counts.keys().map(|p| p.x).max().unwrap_or(0),
+        y: counts.keys().map(|p| p.y).max().unwrap_or(0),
+    };
+    (min, max)
+}
+
+/// Draws the coverage like the puzzle does, with `.` for uncovered points and
+/// the number of lines otherwise, or `#` from ten on.
+fn render(counts: &HashMap<Point, usize>) -> Result<String, MapTooLarge> {
+    let (min, max) = map_bounds(counts)?;
+    let mut output = String::new();
+    for y in min.y..=max.y {
+        for x in min.x..=max.x {
+            output.push(match counts.get(&Point { x, y }) {
+                None => '.',
+                Some(c) if *c < 10 => char::from_digit(*c as u32, 10).unwrap(),
+                Some(_) => '#',
+            });
         }
+        output.push('\n');
     }
+    Ok(output)
+}
 
-    counts.into_values().filter(|v| v >= &2).count()
+/// Draws the coverage as a binary PGM image, one pixel per point and brighter
+/// the more lines cover it.
+fn render_pgm(counts: &HashMap<Point, usize>) -> Result<Vec<u8>, MapTooLarge> {
+    let (min, max) = map_bounds(counts)?;
+    let hottest = counts.values().copied().max().unwrap_or(1);
+    let mut image = format!("P5\n{} {}\n255\n", max.x - min.x + 1, max.y - min.y + 1).into_bytes();
+    for y in min.y..=max.y {
+        for x in min.x..=max.x {
+            let coverage = counts.get(&Point { x, y }).copied().unwrap_or(0);
+            image.push((coverage * 255 / hottest) as u8);
+        }
+    }
+    Ok(image)
 }
 
-fn problem2(lines: &[Line]) -> usize {
-    let mut counts = HashMap::new();
+/// Counts the lattice points covered by at least `threshold` lines, without
+/// listing them.
+///
+/// Lines along the same infinite line are merged into pieces covered by the
+/// same number of them, so every point is in at most one piece per direction.
+/// A point's coverage is the count of its piece, unless pieces in different
+/// directions cross there, and those crossings are few enough to check in
+/// pairs.
+fn count_overlaps<'a>(lines: impl IntoIterator<Item = &'a Line>, threshold: usize) -> usize {
+    let pieces = pieces(lines);
+
+    let mut crossings: HashMap<Point, Vec<usize>> = HashMap::new();
+    for (i, a) in pieces.iter().enumerate() {
+        for (j, b) in pieces.iter().enumerate().skip(i + 1) {
+            if let Some(point) = a.crossing(b) {
+                crossings.entry(point).or_default().extend([i, j]);
+            }
+        }
+    }
+
+    let mut count: usize = pieces
+        .iter()
+        .filter(|p| p.count >= threshold)
+        .map(|p| p.steps as usize + 1)
+        .sum();
+    for mut indices in crossings.into_values() {
+        indices.sort_unstable();
+        indices.dedup();
+        // Replace the point's contributions from each piece by its own.
+        count -= indices
+            .iter()
+            .filter(|i| pieces[**i].count >= threshold)
+            .count();
+        if indices.iter().map(|i| pieces[*i].count).sum::<usize>() >= threshold {
+            count += 1;
+        }
+    }
+    count
+}
+
+/// A stretch of consecutive points covered by the same number of lines.
+#[derive(Debug)]
+struct Piece {
+    start: Point,
+    step: Point,
+    steps: i64,
+    count: usize,
+}
+
+impl Piece {
+    /// The point where two pieces in different directions cross, if they
+    /// cross at a point they both cover.
+    fn crossing(&self, other: &Piece) -> Option<Point> {
+        let det = self.step.cross(&other.step);
+        if det == 0 {
+            return None;
+        }
+
+        // Solve `self.start + t * self.step == other.start + u * other.step`.
+        let offset = Point {
+            x: other.start.x - self.start.x,
+            y: other.start.y - self.start.y,
+        };
+        let t = offset.cross(&other.step);
+        let u = offset.cross(&self.step);
+        if t % det != 0 || u % det != 0 {
+            return None;
+        }
+        let (t, u) = (t / det, u / det);
+        if !(0..=self.steps as i128).contains(&t) || !(0..=other.steps as i128).contains(&u) {
+            return None;
+        }
+
+        Some(Point {
+            x: self.start.x + t as i64 * self.step.x,
+            y: self.start.y + t as i64 * self.step.y,
+        })
+    }
+}
+
+/// Splits the lines into pieces, merging lines along the same infinite line.
+fn pieces<'a>(lines: impl IntoIterator<Item = &'a Line>) -> Vec<Piece> {
+    // Points along an infinite line are identified by their dot product with
+    // its step, which grows by `step.dot(step)` from one point to the next.
+    let mut collinear: HashMap<_, (Point, Vec<(i128, i32)>)> = HashMap::new();
     for line in lines {
-        for point in line.points_between().into_iter() {
-            let entry = counts.entry(point).or_insert(0);
-            *entry += 1;
+        let (step, steps) = line.step();
+        // Orient lines the same way whichever end they are given from.
+        let (start, step) = if step.x < 0 || (step.x == 0 && step.y < 0) {
+            (
+                line.p2,
+                Point {
+                    x: -step.x,
+                    y: -step.y,
+                },
+            )
+        } else {
+            (line.p1, step)
+        };
+
+        let (_, events) = collinear
+            .entry((step, start.cross(&step)))
+            .or_insert((start, Vec::new()));
+        let position = start.dot(&step);
+        events.push((position, 1));
+        events.push((position + (steps as i128 + 1) * step.dot(&step), -1));
+    }
+
+    let mut pieces = Vec::new();
+    for ((step, _), (origin, mut events)) in collinear {
+        events.sort_unstable();
+        let stride = step.dot(&step);
+        let point_at = |position: i128| {
+            let t = ((position - origin.dot(&step)) / stride) as i64;
+            Point {
+                x: origin.x + t * step.x,
+                y: origin.y + t * step.y,
+            }
+        };
+
+        let mut count = 0;
+        for pair in events.windows(2) {
+            let ((position, change), (next, _)) = (pair[0], pair[1]);
+            count += change;
+            if count > 0 && next > position {
+                pieces.push(Piece {
+                    start: point_at(position),
+                    step,
+                    steps: ((next - position) / stride - 1) as i64,
+                    count: count as usize,
+                });
+            }
         }
     }
+    pieces
+}
 
-    counts.into_values().filter(|v| v >= &2).count()
+fn gcd(a: i64, b: i64) -> i64 {
+    if b == 0 {
+        a.abs()
+    } else {
+        gcd(b, a % b)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::rng::Rng;
 
     const SAMPLE: &'static str = "\
 0,9 -> 5,9
@@ -126,4 +505,160 @@ mod tests {
         let lines = parse_lines(SAMPLE);
         assert_eq!(problem2(&lines), 12);
     }
+
+    #[test]
+    fn thresholds() {
+        let mut rng = Rng::new(7);
+        let mut next = |n: u64| rng.below(n) as i64;
+
+        for _ in 0..50 {
+            let lines = (0..30)
+                .map(|_| {
+                    let p1 = Point {
+                        x: next(20),
+                        y: next(20),
+                    };
+                    let (dx, dy) = [(1, 0), (0, 1), (1, 1), (1, -1), (-1, 0), (-1, 1), (2, 1)]
+                        [next(7) as usize];
+                    let len = next(15);
+                    let p2 = if next(4) == 0 {
+                        Point {
+                            x: next(20),
+                            y: next(20),
+                        }
+                    } else {
+                        Point {
+                            x: p1.x + len * dx,
+                            y: p1.y + len * dy,
+                        }
+                    };
+                    Line { p1, p2 }
+                })
+                .collect::<Vec<_>>();
+            for threshold in 1..=4 {
+                let expected = counts(&lines, Coverage::Exact)
+                    .into_values()
+                    .filter(|v| *v >= threshold)
+                    .count();
+                assert_eq!(count_overlaps(&lines, threshold), expected);
+            }
+        }
+
+        let lines = parse_lines(SAMPLE);
+        assert_eq!(count_overlaps(&lines, 1), 39);
+        assert_eq!(count_overlaps(&lines, 3), 2);
+    }
+
+    #[test]
+    fn large_coordinates() {
+        let lines = parse_lines(
+            "0,0 -> 1000000000000,0
+1000000000000,0 -> 100000000000,0
+500000000000,-5 -> 500000000000,5
+-3000000000000,-3000000000005 -> 3000000000000,2999999999995",
+        );
+        assert_eq!(count_overlaps(&lines, 2), 900000000002);
+        assert_eq!(count_overlaps(&lines, 3), 1);
+    }
+
+    #[test]
+    fn any_slope() {
+        let line: Line = "0,0 -> 6,4".parse().unwrap();
+        assert_eq!(
+            line.points(),
+            vec![
+                Point { x: 0, y: 0 },
+                Point { x: 3, y: 2 },
+                Point { x: 6, y: 4 }
+            ]
+        );
+        let line: Line = "5,5 -> 5,5".parse().unwrap();
+        assert_eq!(line.points(), vec![Point { x: 5, y: 5 }]);
+
+        let lines = parse_lines("0,0 -> 6,4\n6,0 -> 0,4\n0,2 -> 6,2");
+        assert_eq!(count_overlaps(&lines, 2), 1);
+        assert_eq!(count_overlaps(&lines, 3), 1);
+    }
+
+    #[test]
+    fn bresenham() {
+        let line: Line = "0,0 -> 4,2".parse().unwrap();
+        let cells = line
+            .cells()
+            .into_iter()
+            .map(|p| (p.x, p.y))
+            .collect::<Vec<_>>();
+        assert_eq!(cells, vec![(0, 0), (1, 1), (2, 1), (3, 2), (4, 2)]);
+
+        // Bresenham and exact coverage agree for the puzzle's lines.
+        let lines = parse_lines(SAMPLE);
+        assert_eq!(
+            counts(&lines, Coverage::Bresenham),
+            counts(&lines, Coverage::Exact)
+        );
+    }
+
+    #[test]
+    fn queries() {
+        let counts = counts(&parse_lines(SAMPLE), Coverage::Exact);
+        assert_eq!(
+            render(&counts).unwrap(),
+            "\
+1.1....11.
+.111...2..
+..2.1.111.
+...1.2.2..
+.112313211
+...1.2....
+..1...1...
+.1.....1..
+1.......1.
+222111....
+"
+        );
+
+        let hottest = hottest(&counts, 2);
+        assert_eq!(
+            hottest,
+            vec![(Point { x: 4, y: 4 }, 3), (Point { x: 6, y: 4 }, 3)]
+        );
+        assert_eq!(
+            histogram(&counts).into_iter().collect::<Vec<_>>(),
+            vec![(1, 27), (2, 10), (3, 2)]
+        );
+        let corner = |x, y| Point { x, y };
+        assert_eq!(overlaps_within(&counts, corner(4, 4), corner(0, 0), 2), 3);
+        assert_eq!(overlaps_within(&counts, corner(0, 0), corner(9, 9), 2), 12);
+
+        let image = render_pgm(&counts).unwrap();
+        assert!(image.starts_with(b"P5\n10 10\n255\n"));
+        assert_eq!(image.len(), 13 + 100);
+        assert_eq!(image[13 + 4 * 10 + 4], 255);
+        assert_eq!(image[13 + 1], 0);
+
+        let far = HashMap::from([
+            (
+                Point {
+                    x: -3_000_000_000_000,
+                    y: 0,
+                },
+                1,
+            ),
+            (
+                Point {
+                    x: 3_000_000_000_000,
+                    y: 1,
+                },
+                1,
+            ),
+        ]);
+        let too_large = MapTooLarge {
+            width: 6_000_000_000_001,
+            height: 2,
+        };
+        assert_eq!(render(&far), Err(too_large));
+        assert!(render_pgm(&far).is_err());
+        let busy = HashMap::from([(Point { x: 0, y: 0 }, (1 << 32) + 1)]);
+        assert_eq!(render(&busy).unwrap(), "#\n");
+    }
 }
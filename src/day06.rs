@@ -1,63 +1,606 @@
+use crate::bigint::BigUint;
 use std::fs;
-use std::mem;
+use std::num::NonZeroU64;
+use std::str::FromStr;
 
 pub fn run() {
     let content = fs::read_to_string("input/day6").expect("Unable to read input");
-    let input = parse_input(&content);
+    let input = parse_input(&content).expect("Invalid input");
 
     println!("Day 6");
     println!("  Problem 1: {}", simulate(&input, 80));
     println!("  Problem 2: {}", simulate(&input, 256));
 }
 
-fn parse_input(raw: &str) -> Vec<usize> {
+/// Prints how many lanternfish there are after `days` days, starting from the
+/// school in the file at `path`, exactly or modulo `modulus`.
+pub fn count_command(path: &str, days: &str, modulus: Option<&str>) -> Result<(), String> {
+    let lifecycle = Lifecycle::default();
+    let input = read_school(path, &lifecycle)?;
+    let days = parse_days(days)?;
+
+    match modulus {
+        Some(modulus) => {
+            let modulus = modulus
+                .parse()
+                .map_err(|_| format!("Modulus must be a positive integer: {}", modulus))?;
+            println!("{}", population(&input, days, &lifecycle, &Modulo(modulus)));
+        }
+        None => println!("{}", population(&input, days, &lifecycle, &Exact)),
+    }
+    Ok(())
+}
+
+/// Prints how many lanternfish there are on each of the first `days` days,
+/// starting from the school in the file at `path`. `options` configure the
+/// lifecycle as `cycle=<days>`, `delay=<days>`, `offspring=<fish>` or
+/// `lifespan=<days>`.
+pub fn history_command(path: &str, days: &str, options: &[&str]) -> Result<(), String> {
+    let lifecycle = parse_lifecycle(options)?;
+    let input = read_school(path, &lifecycle)?;
+    let days = parse_days(days)?;
+
+    for (day, count) in history(&input, days, &lifecycle, &Exact).iter().enumerate() {
+        println!("{},{}", day, count);
+    }
+    Ok(())
+}
+
+/// Prints the first day on which there are more than `threshold` lanternfish,
+/// starting from the school in the file at `path`, and how fast the school
+/// grows in the long run. `options` are as for `history_command`.
+pub fn when_command(path: &str, threshold: &str, options: &[&str]) -> Result<(), String> {
+    let lifecycle = parse_lifecycle(options)?;
+    let input = read_school(path, &lifecycle)?;
+    let threshold = threshold
+        .parse()
+        .map_err(|_| format!("Invalid threshold: {}", threshold))?;
+
+    match first_day_exceeding(&input, &threshold, &lifecycle) {
+        Some(day) => println!("Day {}", day),
+        None => println!("Never"),
+    }
+    println!("Growth rate: {:.6} per day", growth_rate(&lifecycle));
+    Ok(())
+}
+
+/// Reads the school in the file at `path`, checking that its timers fit the
+/// lifecycle.
+fn read_school(path: &str, lifecycle: &Lifecycle) -> Result<Vec<usize>, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("Unable to read school: {}", e))?;
+    let input = parse_input(&content)?;
+    match input.iter().find(|timer| **timer >= lifecycle.timers()) {
+        Some(timer) => Err(format!("Timer {} too long for the lifecycle", timer)),
+        None => Ok(input),
+    }
+}
+
+fn parse_days<T: FromStr>(days: &str) -> Result<T, String> {
+    days.parse()
+        .map_err(|_| format!("Invalid number of days: {}", days))
+}
+
+fn parse_lifecycle(options: &[&str]) -> Result<Lifecycle, String> {
+    let mut lifecycle = Lifecycle::default();
+    for option in options {
+        let (key, value) = option
+            .split_once('=')
+            .ok_or_else(|| format!("Invalid option: {}", option))?;
+        let invalid = |_| format!("Invalid {}: {}", key, value);
+        match key {
+            "cycle" => lifecycle.cycle = value.parse().map_err(invalid)?,
+            "delay" => lifecycle.newborn_delay = value.parse().map_err(invalid)?,
+            "offspring" => lifecycle.offspring = value.parse().map_err(invalid)?,
+            "lifespan" => lifecycle.lifespan = Some(value.parse().map_err(invalid)?),
+            _ => return Err(format!("Unknown option: {}", key)),
+        }
+    }
+    if lifecycle.cycle == 0 || lifecycle.lifespan == Some(0) {
+        return Err("The cycle and lifespan must be at least a day".to_string());
+    }
+    Ok(lifecycle)
+}
+
+fn parse_input(raw: &str) -> Result<Vec<usize>, String> {
     raw.trim_end()
         .split(',')
-        .map(|x| x.parse().unwrap())
+        .map(|x| x.parse().map_err(|_| format!("Invalid timer: {}", x)))
         .collect()
 }
 
 fn simulate(input: &[usize], days: usize) -> usize {
-    const CYCLE_TIME: usize = 7;
-    const BABY_CYCLE_TIME: usize = CYCLE_TIME + 2;
-    let mut population = [0; BABY_CYCLE_TIME];
+    let history = history(input, days, &Lifecycle::default(), &Exact);
+    history[days].to_u64().expect("Too many fish") as usize
+}
+
+/// How lanternfish age and multiply.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Lifecycle {
+    /// Days between an adult's spawns.
+    cycle: usize,
+    /// Extra days before a newborn first spawns.
+    newborn_delay: usize,
+    /// Fish spawned at once.
+    offspring: u64,
+    /// Days a fish lives, counted from the first day for the initial school.
+    /// Fish do not spawn on the day they die.
+    lifespan: Option<usize>,
+}
+
+impl Default for Lifecycle {
+    /// The puzzle's lifecycle.
+    fn default() -> Self {
+        Self {
+            cycle: 7,
+            newborn_delay: 2,
+            offspring: 1,
+            lifespan: None,
+        }
+    }
+}
+
+impl Lifecycle {
+    fn timers(&self) -> usize {
+        self.cycle + self.newborn_delay
+    }
+
+    /// How many ages fish are told apart by, which is only needed with a
+    /// lifespan.
+    fn ages(&self) -> usize {
+        self.lifespan.unwrap_or(1)
+    }
+
+    /// How many states fish can be in, by age and then timer.
+    fn states(&self) -> usize {
+        self.ages() * self.timers()
+    }
+
+    fn state(&self, age: usize, timer: usize) -> usize {
+        age * self.timers() + timer
+    }
+
+    /// For each state, the states a fish in it accounts for the next day and
+    /// how many fish in each.
+    fn transitions(&self) -> Vec<Vec<(usize, u64)>> {
+        assert!(self.cycle > 0 && self.lifespan != Some(0));
 
-    for i in input.iter() {
-        population[*i] += 1;
+        let mut transitions = vec![Vec::new(); self.states()];
+        for age in 0..self.ages() {
+            let next_age = match self.lifespan {
+                Some(lifespan) if age + 1 == lifespan => continue,
+                Some(_) => age + 1,
+                None => 0,
+            };
+            for timer in 0..self.timers() {
+                let next = &mut transitions[self.state(age, timer)];
+                if timer == 0 {
+                    next.push((self.state(next_age, self.cycle - 1), 1));
+                    next.push((self.state(0, self.timers() - 1), self.offspring));
+                } else {
+                    next.push((self.state(next_age, timer - 1), 1));
+                }
+            }
+        }
+        transitions
+    }
+
+    /// How many fish of the initial school are in each state.
+    fn initial<R: Ring>(&self, input: &[usize], ring: &R) -> Vec<R::Elem> {
+        let mut fish = vec![0; self.states()];
+        for i in input.iter() {
+            assert!(*i < self.timers(), "Timer {} too long for the lifecycle", i);
+            fish[self.state(0, *i)] += 1;
+        }
+        fish.into_iter().map(|count| ring.number(count)).collect()
     }
+}
 
+/// How many fish there are on each day up to `days`, simulating day by day.
+fn history<R: Ring>(input: &[usize], days: usize, lifecycle: &Lifecycle, ring: &R) -> Vec<R::Elem> {
+    let transitions = lifecycle.transitions();
+    let mut fish = lifecycle.initial(input, ring);
+    let mut history = vec![total(&fish, ring)];
     for _ in 0..days {
-        let mut carryover = 0;
-        for i in (0..BABY_CYCLE_TIME).rev() {
-            if i > 0 {
-                mem::swap(&mut carryover, &mut population[i]);
-            } else {
-                population[BABY_CYCLE_TIME - 1] = population[0];
-                population[CYCLE_TIME - 1] += population[0];
-                population[0] = carryover;
+        fish = step(&fish, &transitions, ring);
+        history.push(total(&fish, ring));
+    }
+    history
+}
+
+/// The first day on which there are more than `threshold` fish, or `None` if
+/// there never are.
+fn first_day_exceeding(
+    input: &[usize],
+    threshold: &BigUint,
+    lifecycle: &Lifecycle,
+) -> Option<usize> {
+    let transitions = lifecycle.transitions();
+    // A school that never passes the threshold stays within finitely many
+    // states, so it eventually repeats one and never gets larger than it
+    // has been. A growing school never repeats, so it passes it instead.
+    // Brent's algorithm finds the repeat keeping a single earlier state: the
+    // one from the last day that is a power of two.
+    let mut fish = lifecycle.initial(input, &Exact);
+    let mut saved = fish.clone();
+    let mut saved_day = 0;
+    for day in 0.. {
+        if total(&fish, &Exact) > *threshold {
+            return Some(day);
+        }
+        if day > saved_day && fish == saved {
+            return None;
+        }
+        if day.is_power_of_two() {
+            saved = fish.clone();
+            saved_day = day;
+        }
+        fish = step(&fish, &transitions, &Exact);
+    }
+    unreachable!()
+}
+
+/// The factor by which the school eventually grows each day, which is the
+/// dominant eigenvalue of the transition matrix.
+fn growth_rate(lifecycle: &Lifecycle) -> f64 {
+    // Power iteration, on the average of the transition matrix and the
+    // identity so that lifecycles where every fish spawns in step do not make
+    // it oscillate. That halves the distance of the eigenvalue from 1.
+    let transitions = lifecycle.transitions();
+    let mut fish = vec![1.0 / transitions.len() as f64; transitions.len()];
+    let mut rate = 0.0;
+    for _ in 0..100_000 {
+        let mut next = fish.iter().map(|f| f / 2.0).collect::<Vec<_>>();
+        for (count, targets) in fish.iter().zip(&transitions) {
+            for (target, multiplicity) in targets {
+                next[*target] += count * *multiplicity as f64 / 2.0;
             }
         }
+
+        rate = next.iter().sum::<f64>();
+        let next = next.into_iter().map(|f| f / rate).collect::<Vec<_>>();
+        let change = next
+            .iter()
+            .zip(&fish)
+            .map(|(a, b)| (a - b).abs())
+            .sum::<f64>();
+        fish = next;
+        if change < 1e-14 {
+            break;
+        }
+    }
+    2.0 * rate - 1.0
+}
+
+/// How many fish there are in each state the day after there are `fish`.
+fn step<R: Ring>(fish: &[R::Elem], transitions: &[Vec<(usize, u64)>], ring: &R) -> Vec<R::Elem> {
+    let mut next = vec![ring.number(0); fish.len()];
+    for (count, targets) in fish.iter().zip(transitions) {
+        for (target, multiplicity) in targets {
+            let added = ring.mul(count, &ring.number(*multiplicity));
+            next[*target] = ring.add(&next[*target], &added);
+        }
+    }
+    next
+}
+
+fn total<R: Ring>(fish: &[R::Elem], ring: &R) -> R::Elem {
+    fish.iter().fold(ring.number(0), |sum, f| ring.add(&sum, f))
+}
+
+/// Numbers fish can be counted in.
+trait Ring {
+    type Elem: Clone;
+    fn number(&self, v: u64) -> Self::Elem;
+    fn add(&self, a: &Self::Elem, b: &Self::Elem) -> Self::Elem;
+    fn mul(&self, a: &Self::Elem, b: &Self::Elem) -> Self::Elem;
+}
+
+/// Exact counts, whose size grows linearly with the number of days.
+struct Exact;
+
+impl Ring for Exact {
+    type Elem = BigUint;
+
+    fn number(&self, v: u64) -> BigUint {
+        BigUint::from(v)
+    }
+
+    fn add(&self, a: &BigUint, b: &BigUint) -> BigUint {
+        a.add(b)
+    }
+
+    fn mul(&self, a: &BigUint, b: &BigUint) -> BigUint {
+        a.mul(b)
+    }
+}
+
+/// Counts modulo a number.
+struct Modulo(NonZeroU64);
+
+impl Ring for Modulo {
+    type Elem = u64;
+
+    fn number(&self, v: u64) -> u64 {
+        v % self.0
+    }
+
+    fn add(&self, a: &u64, b: &u64) -> u64 {
+        ((*a as u128 + *b as u128) % self.0.get() as u128) as u64
+    }
+
+    fn mul(&self, a: &u64, b: &u64) -> u64 {
+        ((*a as u128 * *b as u128) % self.0.get() as u128) as u64
+    }
+}
+
+type Matrix<T> = Vec<Vec<T>>;
+
+/// How many fish there are after `days` days, from the `days`th power of the
+/// matrix taking the number of fish per state from one day to the next. Its
+/// size grows with the lifespan, so this suits long lifespans poorly.
+fn population<R: Ring>(input: &[usize], days: u64, lifecycle: &Lifecycle, ring: &R) -> R::Elem {
+    // `transition[i][j]` is how many fish in state `i` a fish in state `j`
+    // accounts for the next day.
+    let states = lifecycle.states();
+    let mut transition = vec![vec![ring.number(0); states]; states];
+    for (from, targets) in lifecycle.transitions().into_iter().enumerate() {
+        for (to, multiplicity) in targets {
+            let added = ring.number(multiplicity);
+            transition[to][from] = ring.add(&transition[to][from], &added);
+        }
+    }
+
+    let fish = lifecycle.initial(input, ring);
+    let power = matrix_power(transition, days, ring);
+    let mut total = ring.number(0);
+    for row in power.iter() {
+        for (entry, count) in row.iter().zip(&fish) {
+            total = ring.add(&total, &ring.mul(entry, count));
+        }
+    }
+    total
+}
+
+fn matrix_power<R: Ring>(
+    mut base: Matrix<R::Elem>,
+    mut exponent: u64,
+    ring: &R,
+) -> Matrix<R::Elem> {
+    let n = base.len();
+    let mut result = (0..n)
+        .map(|i| (0..n).map(|j| ring.number((i == j) as u64)).collect())
+        .collect();
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = matrix_multiply(&result, &base, ring);
+        }
+        exponent >>= 1;
+        if exponent > 0 {
+            base = matrix_multiply(&base, &base, ring);
+        }
     }
+    result
+}
 
-    population.iter().sum()
+fn matrix_multiply<R: Ring>(a: &Matrix<R::Elem>, b: &Matrix<R::Elem>, ring: &R) -> Matrix<R::Elem> {
+    let n = a.len();
+    (0..n)
+        .map(|i| {
+            (0..n)
+                .map(|j| {
+                    (0..n).fold(ring.number(0), |sum, k| {
+                        ring.add(&sum, &ring.mul(&a[i][k], &b[k][j]))
+                    })
+                })
+                .collect()
+        })
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn modulo(modulus: u64) -> Modulo {
+        Modulo(NonZeroU64::new(modulus).unwrap())
+    }
+
     const SAMPLE: &'static str = "3,4,3,1,2";
 
     #[test]
     fn example1() {
-        let input = parse_input(SAMPLE);
+        let input = parse_input(SAMPLE).unwrap();
         assert_eq!(simulate(&input, 18), 26);
         assert_eq!(simulate(&input, 80), 5934);
     }
 
     #[test]
     fn example2() {
-        let input = parse_input(SAMPLE);
+        let input = parse_input(SAMPLE).unwrap();
         assert_eq!(simulate(&input, 256), 26984457539);
     }
+
+    #[test]
+    fn matrix() {
+        let input = parse_input(SAMPLE).unwrap();
+        let lifecycle = Lifecycle::default();
+        for days in 0..=256 {
+            let expected = simulate(&input, days) as u64;
+            assert_eq!(
+                population(&input, days as u64, &lifecycle, &Exact).to_u64(),
+                Some(expected)
+            );
+            assert_eq!(
+                population(&input, days as u64, &lifecycle, &modulo(1_000_000_007)),
+                expected % 1_000_000_007
+            );
+        }
+
+        let exact = population(&input, 2000, &lifecycle, &Exact);
+        let modulus = 4_000_000_000;
+        let expected = exact
+            .to_string()
+            .bytes()
+            .fold(0u64, |r, d| (r * 10 + (d - b'0') as u64) % modulus);
+        assert_eq!(
+            population(&input, 2000, &lifecycle, &modulo(modulus)),
+            expected
+        );
+
+        // Only checks that huge day counts are fast.
+        population(
+            &input,
+            1_000_000_000_000_000_000,
+            &lifecycle,
+            &modulo(1_000_000_007),
+        );
+    }
+
+    /// Simulates every fish on its own, as `(timer, age)`.
+    fn simulate_fish(input: &[usize], days: usize, lifecycle: &Lifecycle) -> Vec<u64> {
+        let mut fish = input.iter().map(|t| (*t, 0)).collect::<Vec<_>>();
+        let mut history = vec![fish.len() as u64];
+        for _ in 0..days {
+            let mut next = Vec::new();
+            for (timer, age) in fish {
+                if lifecycle.lifespan == Some(age + 1) {
+                    continue;
+                }
+                if timer == 0 {
+                    next.push((lifecycle.cycle - 1, age + 1));
+                    for _ in 0..lifecycle.offspring {
+                        next.push((lifecycle.timers() - 1, 0));
+                    }
+                } else {
+                    next.push((timer - 1, age + 1));
+                }
+            }
+            fish = next;
+            history.push(fish.len() as u64);
+        }
+        history
+    }
+
+    #[test]
+    fn lifecycles() {
+        let input = parse_input(SAMPLE).unwrap();
+        for lifecycle in [
+            Lifecycle::default(),
+            Lifecycle {
+                cycle: 4,
+                newborn_delay: 1,
+                offspring: 2,
+                lifespan: None,
+            },
+            Lifecycle {
+                cycle: 5,
+                newborn_delay: 0,
+                offspring: 1,
+                lifespan: Some(12),
+            },
+            Lifecycle {
+                cycle: 4,
+                newborn_delay: 3,
+                offspring: 3,
+                lifespan: Some(9),
+            },
+        ] {
+            let expected = simulate_fish(&input, 40, &lifecycle);
+            let history = history(&input, 40, &lifecycle, &Exact)
+                .into_iter()
+                .map(|c| c.to_u64().unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(history, expected, "{:?}", lifecycle);
+            assert_eq!(
+                population(&input, 40, &lifecycle, &modulo(u64::MAX)),
+                expected[40]
+            );
+        }
+    }
+
+    #[test]
+    fn thresholds() {
+        let input = parse_input(SAMPLE).unwrap();
+        let lifecycle = Lifecycle::default();
+        let threshold = |t: u64| BigUint::from(t);
+        assert_eq!(
+            first_day_exceeding(&input, &threshold(4), &lifecycle),
+            Some(0)
+        );
+        assert_eq!(
+            first_day_exceeding(&input, &threshold(5933), &lifecycle),
+            Some(80)
+        );
+        assert_eq!(
+            first_day_exceeding(&input, &threshold(5934), &lifecycle),
+            Some(81)
+        );
+        let day = first_day_exceeding(&input, &threshold(1_000_000_000_000), &lifecycle).unwrap();
+        assert!(simulate(&input, day - 1) <= 1_000_000_000_000);
+        assert!(simulate(&input, day) > 1_000_000_000_000);
+
+        let huge = format!("1{}", "0".repeat(300)).parse::<BigUint>().unwrap();
+        let day = first_day_exceeding(&input, &huge, &lifecycle).unwrap();
+        assert!(population(&input, day as u64 - 1, &lifecycle, &Exact) <= huge);
+        assert!(population(&input, day as u64, &lifecycle, &Exact) > huge);
+
+        let barren = Lifecycle {
+            offspring: 0,
+            ..Lifecycle::default()
+        };
+        assert_eq!(first_day_exceeding(&input, &threshold(5), &barren), None);
+        let short_lived = Lifecycle {
+            lifespan: Some(3),
+            ..Lifecycle::default()
+        };
+        assert_eq!(
+            first_day_exceeding(&input, &threshold(5), &short_lived),
+            Some(2)
+        );
+        assert_eq!(
+            first_day_exceeding(&input, &threshold(6), &short_lived),
+            None
+        );
+
+        // Rounding puts the growth rate of these just above 1.
+        let childless = Lifecycle {
+            cycle: 2,
+            newborn_delay: 1,
+            offspring: 0,
+            lifespan: None,
+        };
+        assert_eq!(
+            first_day_exceeding(&[1, 2], &threshold(5), &childless),
+            None
+        );
+        let mortal = Lifecycle {
+            cycle: 3,
+            newborn_delay: 0,
+            offspring: 1,
+            lifespan: Some(5),
+        };
+        assert_eq!(first_day_exceeding(&[0], &threshold(1000), &mortal), None);
+    }
+
+    #[test]
+    fn growth_rates() {
+        // The rate is the largest root of `x^9 = x^2 + 1`.
+        let rate = growth_rate(&Lifecycle::default());
+        assert!((rate.powi(9) - rate.powi(2) - 1.0).abs() < 1e-9, "{}", rate);
+        assert!((rate - 1.091).abs() < 1e-3);
+
+        let in_step = Lifecycle {
+            cycle: 3,
+            newborn_delay: 0,
+            offspring: 1,
+            lifespan: None,
+        };
+        assert!((growth_rate(&in_step) - 2f64.powf(1.0 / 3.0)).abs() < 1e-9);
+
+        let barren = Lifecycle {
+            offspring: 0,
+            ..Lifecycle::default()
+        };
+        assert!((growth_rate(&barren) - 1.0).abs() < 1e-9);
+    }
 }
@@ -1,65 +1,456 @@
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
 
 pub fn run() {
     let content = fs::read_to_string("input/day7").expect("Unable to read input");
-    let input = parse_input(&content);
+    let input = parse_input(&content).expect("Invalid input");
 
     println!("Day 7");
     println!("  Problem 1: {}", problem1(&input));
     println!("  Problem 2: {}", problem2(&input));
 }
 
-fn parse_input(raw: &str) -> Vec<i32> {
+/// Prints where the crabs in the file at `path` line up for the least fuel
+/// under a cost `model`: `linear`, `triangular`, `quadratic` or `power=<k>`
+/// for the distance raised to the `k`th power. `options` are `key=value`
+/// pairs: `weights` names a file of per-crab fuel multipliers in the same
+/// format as the crabs, `allowed` lists comma-separated positions the crabs
+/// may line up at and `groups` splits them between that many meeting points.
+pub fn align_command(path: &str, model: &str, options: &[&str]) -> Result<(), String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("Unable to read crabs: {}", e))?;
+    let input = parse_input(&content)?;
+    let model = match model.split_once('=') {
+        Some(("power", k)) => match k.parse::<u32>() {
+            Ok(k) if k > 0 => CostModel::Custom(Box::new(move |distance| distance.pow(k))),
+            _ => return Err(format!("Power must be a positive integer: {}", k)),
+        },
+        _ => match model {
+            "linear" => CostModel::Linear,
+            "triangular" => CostModel::Triangular,
+            "quadratic" => CostModel::Quadratic,
+            _ => return Err(format!("Unknown cost model: {}", model)),
+        },
+    };
+
+    let mut constraints = Constraints::default();
+    let mut groups = None;
+    for option in options {
+        let (key, value) = option
+            .split_once('=')
+            .ok_or_else(|| format!("Invalid option: {}", option))?;
+        match key {
+            "weights" => {
+                let weights = fs::read_to_string(value)
+                    .map_err(|e| format!("Unable to read weights: {}", e))?;
+                constraints.weights = Some(parse_input(&weights)?);
+            }
+            "allowed" => constraints.allowed = Some(parse_input(value)?.into_iter().collect()),
+            "groups" => match value.parse() {
+                Ok(k) if k > 0 => groups = Some(k),
+                _ => return Err(format!("Groups must be a positive integer: {}", value)),
+            },
+            _ => return Err(format!("Unknown option: {}", key)),
+        }
+    }
+    if let Some(weights) = &constraints.weights {
+        if weights.len() != input.len() {
+            return Err("Need one weight per crab".to_string());
+        }
+        if weights.iter().any(|w| *w <= 0) {
+            return Err("Weights must be positive".to_string());
+        }
+    }
+
+    match groups {
+        Some(k) => {
+            let groups = meet(&input, k, &constraints, &model);
+            for group in &groups {
+                println!(
+                    "Position {}: {} crabs, fuel {}",
+                    group.alignment.position,
+                    group.crabs.len(),
+                    group.alignment.fuel
+                );
+            }
+            let fuel = groups.iter().map(|g| g.alignment.fuel).sum::<i64>();
+            println!("Fuel: {}", fuel);
+        }
+        None => {
+            let alignment = align_constrained(&input, &constraints, &model);
+            println!("Position: {}", alignment.position);
+            println!("Fuel: {}", alignment.fuel);
+        }
+    }
+    Ok(())
+}
+
+fn parse_input(raw: &str) -> Result<Vec<i64>, String> {
     raw.trim_end()
         .split(',')
-        .map(|x| x.parse().unwrap())
+        .map(|x| x.parse().map_err(|_| format!("Invalid number: {}", x)))
         .collect()
 }
 
-fn problem1(input: &[i32]) -> i32 {
-    let mut input: Vec<_> = input.iter().collect();
-    input.sort();
+fn problem1(input: &[i64]) -> i64 {
+    align(input, &CostModel::Linear).fuel
+}
+
+fn problem2(input: &[i64]) -> i64 {
+    align(input, &CostModel::Triangular).fuel
+}
+
+/// How much fuel a crab spends to move a given distance.
+enum CostModel {
+    /// One unit of fuel per step.
+    Linear,
+    /// One more unit of fuel for each step than for the previous one.
+    Triangular,
+    /// The square of the distance.
+    Quadratic,
+    /// Any cost that is convex in the distance, which is what lets the best
+    /// position be found without trying every one.
+    Custom(Box<dyn Fn(i64) -> i64>),
+}
+
+impl CostModel {
+    fn cost(&self, distance: i64) -> i64 {
+        match self {
+            CostModel::Linear => distance,
+            CostModel::Triangular => distance * (distance + 1) / 2,
+            CostModel::Quadratic => distance * distance,
+            CostModel::Custom(cost) => cost(distance),
+        }
+    }
+}
+
+/// Where the crabs line up, and the fuel they spend getting there.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Alignment {
+    position: i64,
+    fuel: i64,
+}
+
+/// Restrictions on how the crabs line up.
+#[derive(Default)]
+struct Constraints {
+    /// How many times the fuel of the cost model each crab spends, in the
+    /// order of the input. Every crab spends it once if not given.
+    weights: Option<Vec<i64>>,
+    /// The positions the crabs may line up at, any if not given.
+    allowed: Option<BTreeSet<i64>>,
+}
+
+/// The crabs lining up at one of several meeting points.
+#[derive(Debug, PartialEq, Eq)]
+struct Group {
+    alignment: Alignment,
+    /// Indices of the crabs in the input, in order.
+    crabs: Vec<usize>,
+}
+
+/// The position the crabs can line up at for the least fuel.
+fn align(input: &[i64], model: &CostModel) -> Alignment {
+    align_constrained(input, &Constraints::default(), model)
+}
 
-    let median = input[input.len() / 2];
-    input.into_iter().map(|i| (median - i).abs()).sum()
+/// The position the crabs can line up at for the least fuel within the
+/// `constraints`.
+fn align_constrained(input: &[i64], constraints: &Constraints, model: &CostModel) -> Alignment {
+    let crabs = weighted(input, constraints);
+    best(&crabs, constraints.allowed.as_ref(), model)
 }
 
-fn problem2(input: &[i32]) -> i32 {
-    fn calc_use(n: i32) -> i32 {
-        n * (n + 1) / 2
+/// How to split the crabs into `k` groups, each lining up at its own
+/// position, for the least fuel in total.
+///
+/// Whatever the convex cost, the cheapest meeting point for the crabs is
+/// further right the further right they are, so the groups are runs of
+/// crabs in order of position and can be found by dynamic programming over
+/// where each run starts. That takes the best alignment of every run, so
+/// this is cubic in the number of distinct positions.
+fn meet(input: &[i64], k: usize, constraints: &Constraints, model: &CostModel) -> Vec<Group> {
+    assert!(k > 0, "Need at least one meeting point");
+
+    // Crabs at the same position always end up in the same group.
+    let mut positions = BTreeMap::new();
+    for (i, (position, weight)) in weighted(input, constraints).into_iter().enumerate() {
+        let (total, crabs) = positions.entry(position).or_insert((0, Vec::new()));
+        *total += weight;
+        crabs.push(i);
     }
+    let crabs = positions
+        .iter()
+        .map(|(position, (weight, _))| (*position, *weight))
+        .collect::<Vec<_>>();
+    let n = crabs.len();
+    let k = k.min(n);
+
+    let allowed = constraints.allowed.as_ref();
+    let runs = (0..n)
+        .map(|start| {
+            (start + 1..=n)
+                .map(|end| best(&crabs[start..end], allowed, model))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    let run = |start: usize, end: usize| runs[start][end - start - 1];
 
-    let max = *input.iter().max().unwrap();
-    let mut best_fuel_use = i32::MAX;
-    for candidate in 0..=max {
-        let mut fuel_use = 0;
-        for value in input {
-            fuel_use += calc_use((value - candidate).abs());
+    // The least fuel for the first `end` positions to split into `groups`
+    // groups, and where the last of those groups starts.
+    let mut fuel = vec![vec![None; n + 1]; k + 1];
+    fuel[0][0] = Some((0, 0));
+    for groups in 1..=k {
+        for end in groups..=n {
+            fuel[groups][end] = (groups - 1..end)
+                .filter_map(|start| {
+                    let (before, _) = fuel[groups - 1][start]?;
+                    Some((before + run(start, end).fuel, start))
+                })
+                .min();
         }
+    }
+
+    let mut groups = Vec::new();
+    let mut end = n;
+    let mut members = positions
+        .into_values()
+        .map(|(_, crabs)| crabs)
+        .collect::<Vec<_>>();
+    for remaining in (1..=k).rev() {
+        let (_, start) = fuel[remaining][end].unwrap();
+        let mut crabs = members.drain(start..end).flatten().collect::<Vec<_>>();
+        crabs.sort_unstable();
+        groups.push(Group {
+            alignment: run(start, end),
+            crabs,
+        });
+        end = start;
+    }
+    groups.reverse();
+    groups
+}
 
-        if fuel_use < best_fuel_use {
-            best_fuel_use = fuel_use;
+/// Pairs each crab's position with its weight.
+fn weighted(input: &[i64], constraints: &Constraints) -> Vec<(i64, i64)> {
+    assert!(!input.is_empty(), "No crabs to align");
+    match &constraints.weights {
+        Some(weights) => {
+            assert_eq!(weights.len(), input.len(), "Need one weight per crab");
+            assert!(weights.iter().all(|w| *w > 0), "Weights must be positive");
+            input.iter().copied().zip(weights.iter().copied()).collect()
         }
+        None => input.iter().map(|i| (*i, 1)).collect(),
     }
+}
 
-    best_fuel_use
+/// The fuel the weighted crabs spend to all move to `position`.
+fn fuel(crabs: &[(i64, i64)], position: i64, model: &CostModel) -> i64 {
+    crabs
+        .iter()
+        .map(|(i, weight)| weight * model.cost((i - position).abs()))
+        .sum()
+}
+
+/// The best alignment of the weighted crabs, at one of the `allowed`
+/// positions if given.
+fn best(crabs: &[(i64, i64)], allowed: Option<&BTreeSet<i64>>, model: &CostModel) -> Alignment {
+    let total = crabs.iter().map(|(_, weight)| weight).sum::<i64>();
+    let candidates = match model {
+        CostModel::Linear => {
+            let mut crabs = crabs.to_vec();
+            crabs.sort_unstable();
+            let mut seen = 0;
+            let (median, _) = crabs
+                .into_iter()
+                .find(|(_, weight)| {
+                    seen += weight;
+                    2 * seen > total
+                })
+                .unwrap();
+            median..=median
+        }
+        // The total fuel is then minimised within half a step of the mean,
+        // either side of it for the triangular cost.
+        CostModel::Triangular | CostModel::Quadratic => {
+            let sum = crabs.iter().map(|(i, weight)| i * weight).sum::<i64>();
+            let mean = sum.div_euclid(total);
+            mean - 1..=mean + 1
+        }
+        CostModel::Custom(_) => {
+            let mut low = crabs.iter().map(|(i, _)| *i).min().unwrap();
+            let mut high = crabs.iter().map(|(i, _)| *i).max().unwrap();
+            while high - low > 2 {
+                let third = (high - low) / 3;
+                let (m1, m2) = (low + third, high - third);
+                let (f1, f2) = (fuel(crabs, m1, model), fuel(crabs, m2, model));
+                if f1 < f2 {
+                    high = m2 - 1;
+                } else if f1 > f2 {
+                    low = m1 + 1;
+                } else {
+                    low = m1;
+                    high = m2;
+                }
+            }
+            low..=high
+        }
+    };
+
+    let alignment = |position| Alignment {
+        position,
+        fuel: fuel(crabs, position, model),
+    };
+    let best = candidates.map(alignment).min_by_key(|a| a.fuel).unwrap();
+    match allowed {
+        // The fuel only grows moving away from the best position, so the
+        // best allowed one is the nearest on either side.
+        Some(allowed) => {
+            let below = allowed.range(..=best.position).next_back();
+            let above = allowed.range(best.position..).next();
+            below
+                .into_iter()
+                .chain(above)
+                .map(|position| alignment(*position))
+                .min_by_key(|a| a.fuel)
+                .expect("No allowed positions")
+        }
+        None => best,
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::rng::Rng;
 
     const SAMPLE: &'static str = "16,1,2,0,4,2,7,1,2,14";
 
     #[test]
     fn example1() {
-        let input = parse_input(SAMPLE);
+        let input = parse_input(SAMPLE).unwrap();
         assert_eq!(problem1(&input), 37);
     }
 
     #[test]
     fn example2() {
-        let input = parse_input(SAMPLE);
+        let input = parse_input(SAMPLE).unwrap();
         assert_eq!(problem2(&input), 168);
     }
+
+    #[test]
+    fn cost_models() {
+        let input = parse_input(SAMPLE).unwrap();
+        let alignment = |position, fuel| Alignment { position, fuel };
+        assert_eq!(align(&input, &CostModel::Linear), alignment(2, 37));
+        assert_eq!(align(&input, &CostModel::Triangular), alignment(5, 168));
+        assert_eq!(align(&input, &CostModel::Quadratic), alignment(5, 291));
+
+        let mut random = random(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..200 {
+            let n = 1 + random(20) as usize;
+            let input = (0..n).map(|_| random(100) - 50).collect::<Vec<_>>();
+            let models = [
+                CostModel::Linear,
+                CostModel::Triangular,
+                CostModel::Quadratic,
+                CostModel::Custom(Box::new(|d| d * d * d)),
+                CostModel::Custom(Box::new(|d| (d - 5).max(0))),
+            ];
+            for model in &models {
+                let crabs = input.iter().map(|i| (*i, 1)).collect::<Vec<_>>();
+                let best = (-50..50).map(|p| fuel(&crabs, p, model)).min().unwrap();
+                let alignment = align(&input, model);
+                assert_eq!(alignment.fuel, best, "{:?}", input);
+                assert_eq!(fuel(&crabs, alignment.position, model), best);
+            }
+        }
+    }
+
+    #[test]
+    fn constraints() {
+        let input = parse_input(SAMPLE).unwrap();
+        let alignment = |position, fuel| Alignment { position, fuel };
+        let constraints = Constraints {
+            weights: Some(vec![10, 1, 1, 1, 1, 1, 1, 1, 1, 10]),
+            allowed: None,
+        };
+        let aligned = align_constrained(&input, &constraints, &CostModel::Linear);
+        assert_eq!(aligned, alignment(14, 113));
+        let constraints = Constraints {
+            weights: None,
+            allowed: Some([0, 8].into()),
+        };
+        let aligned = align_constrained(&input, &constraints, &CostModel::Triangular);
+        assert_eq!(aligned, alignment(8, 223));
+
+        let groups = meet(&input, 2, &Constraints::default(), &CostModel::Linear);
+        assert_eq!(
+            groups,
+            vec![
+                Group {
+                    alignment: alignment(2, 11),
+                    crabs: vec![1, 2, 3, 4, 5, 6, 7, 8],
+                },
+                Group {
+                    alignment: alignment(16, 2),
+                    crabs: vec![0, 9],
+                },
+            ]
+        );
+        assert_eq!(
+            meet(&input, 20, &Constraints::default(), &CostModel::Linear).len(),
+            7
+        );
+
+        let mut random = random(0x2545_f491_4f6c_dd1d);
+        for _ in 0..100 {
+            let n = 1 + random(8) as usize;
+            let input = (0..n).map(|_| random(20) - 10).collect::<Vec<_>>();
+            let weights = (0..n).map(|_| 1 + random(4)).collect::<Vec<_>>();
+            let allowed = (-10..10)
+                .filter(|_| random(3) == 0)
+                .collect::<BTreeSet<_>>();
+            let constraints = Constraints {
+                weights: Some(weights.clone()),
+                allowed: (!allowed.is_empty()).then(|| allowed.clone()),
+            };
+            let positions = constraints
+                .allowed
+                .clone()
+                .unwrap_or_else(|| (-10..10).collect());
+            let crabs = input.iter().copied().zip(weights).collect::<Vec<_>>();
+            let models = [
+                CostModel::Linear,
+                CostModel::Triangular,
+                CostModel::Quadratic,
+                CostModel::Custom(Box::new(|d| d * d * d)),
+            ];
+            for model in &models {
+                let best = positions.iter().map(|p| fuel(&crabs, *p, model)).min();
+                let alignment = align_constrained(&input, &constraints, model);
+                assert_eq!(Some(alignment.fuel), best, "{:?}", crabs);
+                assert!(positions.contains(&alignment.position));
+
+                let best = positions
+                    .iter()
+                    .flat_map(|p| positions.iter().map(move |q| (*p, *q)))
+                    .map(|(p, q)| {
+                        let fuel = |(i, w): &(i64, i64)| {
+                            w * model.cost((i - p).abs()).min(model.cost((i - q).abs()))
+                        };
+                        crabs.iter().map(fuel).sum::<i64>()
+                    })
+                    .min();
+                let groups = meet(&input, 2, &constraints, model);
+                let fuel = groups.iter().map(|g| g.alignment.fuel).sum::<i64>();
+                assert_eq!(Some(fuel), best, "{:?}", crabs);
+            }
+        }
+    }
+
+    /// A generator of numbers below its argument.
+    fn random(seed: u64) -> impl FnMut(u64) -> i64 {
+        let mut rng = Rng::new(seed);
+        move |range| rng.below(range) as i64
+    }
 }
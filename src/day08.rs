@@ -1,33 +1,45 @@
+use crate::rng::Rng;
+use std::fmt;
 use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 pub fn run() {
     let content = fs::read_to_string("input/day8").expect("Unable to read input");
-    let lines = parse_lines(&content);
+    let lines = parse_lines(&content).expect("Invalid input");
 
     println!("Day 8");
     println!("  Problem 1: {}", problem1(&lines));
     println!("  Problem 2: {}", problem2(&lines));
 }
 
-fn parse_lines(raw: &str) -> Vec<Entry> {
+fn parse_lines(raw: &str) -> Result<Vec<Entry>, String> {
     let mut parsed = Vec::new();
     for line in raw.lines() {
         let mut parts = line.split(' ');
         let patterns = parts
             .by_ref()
             .take_while(|s| *s != "|")
-            .map(to_bits)
-            .collect();
-        let outputs = parts.map(to_bits).collect();
+            .map(parse_pattern)
+            .collect::<Result<_, _>>()?;
+        let outputs = parts.map(parse_pattern).collect::<Result<_, _>>()?;
         parsed.push(Entry { patterns, outputs });
     }
 
-    parsed
+    Ok(parsed)
+}
+
+/// Reads a pattern of segments named `a` to `z`.
+fn parse_pattern(s: &str) -> Result<u32, String> {
+    if s.bytes().all(|c| c.is_ascii_lowercase()) {
+        Ok(to_bits(s))
+    } else {
+        Err(format!("Invalid pattern: {}", s))
+    }
 }
 
 struct Entry {
-    patterns: Vec<u8>,
-    outputs: Vec<u8>,
+    patterns: Vec<u32>,
+    outputs: Vec<u32>,
 }
 
 fn problem1(lines: &[Entry]) -> usize {
@@ -40,39 +52,296 @@ fn problem1(lines: &[Entry]) -> usize {
 }
 
 fn problem2(lines: &[Entry]) -> u32 {
+    let glyphs = Glyphs::seven_segment();
     let mut sum = 0;
     for line in lines {
-        let one = line.patterns.iter().find(|b| b.count_ones() == 2).unwrap();
-        let four = line.patterns.iter().find(|b| b.count_ones() == 4).unwrap();
-
-        let mut values = [0; 128];
-
-        for b in line.patterns.iter() {
-            values[*b as usize] = match b.count_ones() {
-                2 => 1,
-                3 => 7,
-                4 => 4,
-                5 if b & one == *one => 3,
-                5 if (b & four).count_ones() == 3 => 5,
-                5 => 2,
-                6 if b & one != *one => 6,
-                6 if b & four == *four => 9,
-                6 => 0,
-                7 => 8,
-                _ => unreachable!(),
-            };
-        }
-
         let mut n = 0u32;
-        for output in line.outputs.iter() {
-            n = 10 * n + values[*output as usize];
+        for digit in decode(line, &glyphs).expect("Unable to decode entry") {
+            n = 10 * n + digit as u32;
         }
         sum += n;
     }
     sum
 }
 
-fn to_bits(s: &str) -> u8 {
+/// Prints how each entry in the file at `path` is wired, as the segment
+/// driven by each wire from `a` on, and the digits it reads. The digits are
+/// those of a seven-segment display, or read from the file at `glyphs_path`
+/// as patterns of segments separated by whitespace, in the order of the
+/// digits they show.
+pub fn decode_command(path: &str, glyphs_path: Option<&str>) -> Result<(), String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("Unable to read entries: {}", e))?;
+    let lines = parse_lines(&content)?;
+    let glyphs = match glyphs_path {
+        Some(path) => {
+            let content =
+                fs::read_to_string(path).map_err(|e| format!("Unable to read glyphs: {}", e))?;
+            let digits = content
+                .split_whitespace()
+                .map(parse_pattern)
+                .collect::<Result<Vec<_>, _>>()?;
+            if !Glyphs::uses_every_segment(&digits) {
+                return Err("Glyphs must use every segment from `a` on".to_string());
+            }
+            Glyphs::new(digits)
+        }
+        None => Glyphs::seven_segment(),
+    };
+
+    for (i, line) in lines.iter().enumerate() {
+        let decoded = solve(&line.patterns, &glyphs)
+            .and_then(|wiring| Ok((wiring.clone(), read(line, &wiring, &glyphs)?)));
+        match decoded {
+            Ok((wiring, digits)) => {
+                let wiring: String = wiring.iter().map(|s| (b'a' + *s as u8) as char).collect();
+                let digits: Vec<_> = digits.iter().map(usize::to_string).collect();
+                println!("{}: {} {}", i + 1, wiring, digits.join(" "));
+            }
+            Err(error) => println!("{}: {}", i + 1, error),
+        }
+    }
+    Ok(())
+}
+
+/// Prints an entry whose outputs read `digits` on a seven-segment display
+/// with randomly crossed wires, reproducibly so if given a `seed`.
+pub fn scramble_command(digits: &str, seed: Option<&str>) -> Result<(), String> {
+    let digits: Vec<usize> = digits
+        .chars()
+        .map(|c| match c.to_digit(10) {
+            Some(digit) => Ok(digit as usize),
+            None => Err(format!("Invalid digit: {}", c)),
+        })
+        .collect::<Result<_, _>>()?;
+    let seed = match seed {
+        Some(seed) => seed
+            .parse()
+            .map_err(|_| format!("Invalid seed: {}", seed))?,
+        None => SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Clock before 1970")
+            .as_nanos() as u64,
+    };
+
+    let glyphs = Glyphs::seven_segment();
+    let mut rng = Rng::new(seed);
+    let wiring = random_wiring(glyphs.segments, &mut rng);
+    println!("{}", scramble(&digits, &wiring, &glyphs, &mut rng));
+    Ok(())
+}
+
+/// The segments lit for each digit of a display.
+struct Glyphs {
+    digits: Vec<u32>,
+    segments: usize,
+}
+
+impl Glyphs {
+    fn new(digits: Vec<u32>) -> Glyphs {
+        assert!(
+            Self::uses_every_segment(&digits),
+            "Glyphs must use every segment from `a` on"
+        );
+        let segments = digits.iter().fold(0, |all, d| all | d);
+        Glyphs {
+            segments: segments.count_ones() as usize,
+            digits,
+        }
+    }
+
+    /// Whether the digits leave no segment before the last one they use unlit.
+    fn uses_every_segment(digits: &[u32]) -> bool {
+        let segments = digits.iter().fold(0, |all, d| all | d);
+        segments.count_ones() == 32 - segments.leading_zeros()
+    }
+
+    fn seven_segment() -> Glyphs {
+        let digits = [
+            "abcefg", "cf", "acdeg", "acdfg", "bcdf", "abdfg", "abdefg", "acf", "abcdefg", "abcdfg",
+        ];
+        Glyphs::new(digits.into_iter().map(to_bits).collect())
+    }
+}
+
+/// Reasons the wiring of an entry cannot be worked out.
+#[derive(Debug, PartialEq, Eq)]
+enum DecodeError {
+    /// No wiring turns the patterns into the digits.
+    Inconsistent,
+    /// More than one wiring does.
+    Ambiguous,
+    /// An output is not one of the patterns.
+    UnknownOutput(u32),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Inconsistent => write!(f, "no wiring matches the patterns"),
+            Self::Ambiguous => write!(f, "more than one wiring matches the patterns"),
+            Self::UnknownOutput(output) => {
+                write!(
+                    f,
+                    "output {} is not one of the patterns",
+                    from_bits(*output)
+                )
+            }
+        }
+    }
+}
+
+/// The digits shown by the outputs of an entry.
+fn decode(entry: &Entry, glyphs: &Glyphs) -> Result<Vec<usize>, DecodeError> {
+    let wiring = solve(&entry.patterns, glyphs)?;
+    read(entry, &wiring, glyphs)
+}
+
+/// The digits shown by the outputs of an entry with the given wiring.
+fn read(entry: &Entry, wiring: &[usize], glyphs: &Glyphs) -> Result<Vec<usize>, DecodeError> {
+    entry
+        .outputs
+        .iter()
+        .map(|output| {
+            if !entry.patterns.contains(output) {
+                return Err(DecodeError::UnknownOutput(*output));
+            }
+            let segments = rewire(*output, wiring);
+            Ok(glyphs.digits.iter().position(|d| *d == segments).unwrap())
+        })
+        .collect()
+}
+
+/// Works out which segment each wire drives, given the patterns of wires lit
+/// for every digit of the display.
+///
+/// Wires are assigned segments one at a time, backtracking whenever the
+/// segments so far rule out every digit a pattern could be. A wire is only
+/// tried on segments lit by as many digits of each size as the wire is.
+fn solve(patterns: &[u32], glyphs: &Glyphs) -> Result<Vec<usize>, DecodeError> {
+    let mut unique = patterns.to_vec();
+    unique.sort_unstable();
+    unique.dedup();
+    let wires = patterns.iter().fold(0, |all, p| all | p);
+    if unique.len() != glyphs.digits.len() || wires >> glyphs.segments != 0 {
+        return Err(DecodeError::Inconsistent);
+    }
+
+    let profile = |shapes: &[u32], bit: usize| {
+        let mut sizes: Vec<_> = shapes
+            .iter()
+            .filter(|s| *s >> bit & 1 == 1)
+            .map(|s| s.count_ones())
+            .collect();
+        sizes.sort_unstable();
+        sizes
+    };
+    let candidates: Vec<Vec<usize>> = (0..glyphs.segments)
+        .map(|wire| {
+            let wanted = profile(patterns, wire);
+            (0..glyphs.segments)
+                .filter(|segment| profile(&glyphs.digits, *segment) == wanted)
+                .collect()
+        })
+        .collect();
+
+    let mut solutions = Vec::new();
+    let mut wiring = Vec::new();
+    search(patterns, glyphs, &candidates, &mut wiring, &mut solutions);
+    match solutions.len() {
+        0 => Err(DecodeError::Inconsistent),
+        1 => Ok(solutions.pop().unwrap()),
+        _ => Err(DecodeError::Ambiguous),
+    }
+}
+
+/// Extends a partial `wiring` of the first wires, stopping once a second
+/// solution shows it is ambiguous.
+fn search(
+    patterns: &[u32],
+    glyphs: &Glyphs,
+    candidates: &[Vec<usize>],
+    wiring: &mut Vec<usize>,
+    solutions: &mut Vec<Vec<usize>>,
+) {
+    if wiring.len() == candidates.len() {
+        let mut digits: Vec<_> = patterns.iter().map(|p| rewire(*p, wiring)).collect();
+        digits.sort_unstable();
+        let mut expected = glyphs.digits.clone();
+        expected.sort_unstable();
+        if digits == expected {
+            solutions.push(wiring.clone());
+        }
+        return;
+    }
+
+    for segment in candidates[wiring.len()].iter() {
+        if wiring.contains(segment) {
+            continue;
+        }
+        wiring.push(*segment);
+
+        let wires = (1 << wiring.len()) - 1;
+        let segments = wiring.iter().fold(0, |all, s| all | 1 << s);
+        let possible = patterns.iter().all(|p| {
+            let lit = rewire(p & wires, wiring);
+            glyphs
+                .digits
+                .iter()
+                .any(|d| d.count_ones() == p.count_ones() && d & segments == lit)
+        });
+        if possible {
+            search(patterns, glyphs, candidates, wiring, solutions);
+        }
+
+        wiring.pop();
+        if solutions.len() > 1 {
+            return;
+        }
+    }
+}
+
+/// The segments lit by a pattern of wires, for the wires that are wired.
+fn rewire(pattern: u32, wiring: &[usize]) -> u32 {
+    wiring
+        .iter()
+        .enumerate()
+        .filter(|(wire, _)| pattern >> wire & 1 == 1)
+        .fold(0, |segments, (_, segment)| segments | 1 << segment)
+}
+
+/// The entry line for a display wired as `wiring` showing the glyphs at
+/// `digits`. The patterns come in random order, and so do the wires within
+/// each of them.
+fn scramble(digits: &[usize], wiring: &[usize], glyphs: &Glyphs, rng: &mut Rng) -> String {
+    assert_eq!(wiring.len(), glyphs.segments, "Need one wire per segment");
+    let pattern = |digit: usize| {
+        let segments = glyphs.digits[digit];
+        (0..wiring.len())
+            .filter(|wire| segments >> wiring[*wire] & 1 == 1)
+            .fold(0, |bits, wire| bits | 1 << wire)
+    };
+    let mut order: Vec<usize> = (0..glyphs.digits.len()).collect();
+    rng.shuffle(&mut order);
+    let mut write = |bits: u32| {
+        let mut wires: Vec<char> = from_bits(bits).chars().collect();
+        rng.shuffle(&mut wires);
+        wires.into_iter().collect::<String>()
+    };
+
+    let mut parts: Vec<String> = order.into_iter().map(|d| write(pattern(d))).collect();
+    parts.push("|".to_string());
+    parts.extend(digits.iter().map(|d| write(pattern(*d))));
+    parts.join(" ")
+}
+
+/// A wiring with each wire driving a random segment.
+fn random_wiring(segments: usize, rng: &mut Rng) -> Vec<usize> {
+    let mut wiring: Vec<usize> = (0..segments).collect();
+    rng.shuffle(&mut wiring);
+    wiring
+}
+
+fn to_bits(s: &str) -> u32 {
     let mut bits = 0;
     for c in s.chars() {
         let shift = c as u8 - b'a';
@@ -81,6 +350,13 @@ fn to_bits(s: &str) -> u8 {
     bits
 }
 
+fn from_bits(bits: u32) -> String {
+    (0..32)
+        .filter(|shift| bits >> shift & 1 == 1)
+        .map(|shift| (b'a' + shift) as char)
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -99,13 +375,93 @@ gcafb gcf dcaebfg ecagb gf abcdeg gaef cafbge fdbac fegbdc | fgae cfgab fg bagce
 
     #[test]
     fn example1() {
-        let lines = parse_lines(SAMPLE);
+        let lines = parse_lines(SAMPLE).unwrap();
         assert_eq!(problem1(&lines), 26);
     }
 
     #[test]
     fn example2() {
-        let lines = parse_lines(SAMPLE);
+        let lines = parse_lines(SAMPLE).unwrap();
         assert_eq!(problem2(&lines), 61229);
     }
+
+    #[test]
+    fn wiring() {
+        let lines = parse_lines(SAMPLE).unwrap();
+        let glyphs = Glyphs::seven_segment();
+        let line = parse_lines(
+            "acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb ab | cdfeb fcadb cdfeb cdbaf",
+        )
+        .unwrap();
+        assert_eq!(
+            solve(&line[0].patterns, &glyphs),
+            Ok(vec![2, 5, 6, 0, 1, 3, 4])
+        );
+        assert_eq!(decode(&line[0], &glyphs), Ok(vec![5, 3, 5, 3]));
+        assert_eq!(decode(&lines[0], &glyphs), Ok(vec![8, 3, 9, 4]));
+
+        let mut entry = parse_lines(SAMPLE).unwrap().remove(0);
+        entry.patterns[0] = to_bits("bc");
+        assert_eq!(decode(&entry, &glyphs), Err(DecodeError::Inconsistent));
+        let mut entry = parse_lines(SAMPLE).unwrap().remove(0);
+        entry.outputs[0] = to_bits("ab");
+        assert_eq!(
+            decode(&entry, &glyphs),
+            Err(DecodeError::UnknownOutput(to_bits("ab")))
+        );
+
+        // Two segments lit by exactly the same digits can't be told apart.
+        let glyphs = Glyphs::new(
+            ["ab", "abc", "c", "abcd"]
+                .into_iter()
+                .map(to_bits)
+                .collect(),
+        );
+        let patterns = ["cd", "acd", "a", "abcd"].map(to_bits);
+        assert_eq!(solve(&patterns, &glyphs), Err(DecodeError::Ambiguous));
+
+        // Sixteen glyphs on a sixteen-segment display, scrambled every which
+        // way.
+        let mut rng = Rng::new(0x853c_49e6_748f_ea9b);
+        let mut digits: Vec<u32> = (0..16).map(|_| rng.next() as u32 & 0xffff).collect();
+        digits[0] = 0xffff;
+        let glyphs = Glyphs::new(digits);
+        for _ in 0..20 {
+            let wiring = random_wiring(16, &mut rng);
+            let patterns: Vec<_> = glyphs
+                .digits
+                .iter()
+                .map(|d| {
+                    (0..16)
+                        .filter(|w| d >> wiring[*w] & 1 == 1)
+                        .fold(0, |p, w| p | 1 << w)
+                })
+                .collect();
+            assert_eq!(solve(&patterns, &glyphs), Ok(wiring));
+        }
+    }
+
+    #[test]
+    fn scrambled() {
+        let glyphs = Glyphs::seven_segment();
+        let mut rng = Rng::new(2021);
+        for _ in 0..1000 {
+            let wiring = random_wiring(7, &mut rng);
+            let digits: Vec<usize> = (0..4).map(|_| (rng.next() % 10) as usize).collect();
+            let line = scramble(&digits, &wiring, &glyphs, &mut rng);
+
+            let entry = parse_lines(&line).unwrap().remove(0);
+            assert_eq!(entry.patterns.len(), 10);
+            assert_eq!(solve(&entry.patterns, &glyphs), Ok(wiring));
+            assert_eq!(decode(&entry, &glyphs), Ok(digits));
+        }
+
+        let line = |seed| {
+            let mut rng = Rng::new(seed);
+            let wiring = random_wiring(7, &mut rng);
+            scramble(&[1, 2, 3, 4], &wiring, &glyphs, &mut rng)
+        };
+        assert_eq!(line(7), line(7));
+        assert_ne!(line(7), line(8));
+    }
 }
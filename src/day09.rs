@@ -2,89 +2,203 @@ use std::fs;
 
 pub fn run() {
     let content = fs::read_to_string("input/day9").expect("Unable to read input");
-    let lines = parse_lines(&content);
+    let lines = parse_lines(&content).expect("Invalid input");
 
     println!("Day 9");
     println!("  Problem 1: {}", problem1(&lines));
     println!("  Problem 2: {}", problem2(&lines));
 }
 
-fn parse_lines(raw: &str) -> Vec<Vec<u8>> {
-    let mut parsed = Vec::new();
+fn parse_lines(raw: &str) -> Result<Vec<Vec<u8>>, String> {
+    let mut parsed: Vec<Vec<u8>> = Vec::new();
     for line in raw.lines() {
-        parsed.push(line.chars().map(|c| c as u8 - b'0').collect());
+        let row = line
+            .chars()
+            .map(|c| match c.to_digit(10) {
+                Some(height) => Ok(height as u8),
+                None => Err(format!("Invalid height: {}", c)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if parsed.first().is_some_and(|first| first.len() != row.len()) {
+            return Err("Rows must all be the same length".to_string());
+        }
+        parsed.push(row);
     }
 
-    parsed
+    Ok(parsed)
+}
+
+/// Prints each basin of the heightmap in the file at `path`, found with 4-
+/// or 8-`connectivity`, and a map of which basin each cell belongs to.
+pub fn basins_command(path: &str, connectivity: Option<&str>) -> Result<(), String> {
+    let content =
+        fs::read_to_string(path).map_err(|e| format!("Unable to read heightmap: {}", e))?;
+    let rows = parse_lines(&content)?;
+    let connectivity = match connectivity {
+        None | Some("4") => Connectivity::Four,
+        Some("8") => Connectivity::Eight,
+        Some(other) => return Err(format!("Unknown connectivity: {}", other)),
+    };
+
+    let basins = Basins::new(&rows, connectivity);
+    for (id, basin) in basins.basins.iter().enumerate() {
+        let (row, col) = basin.low_point;
+        println!(
+            "Basin {}: low point {},{} at height {}, size {}",
+            id,
+            row,
+            col,
+            rows[row][col],
+            basin.size()
+        );
+    }
+
+    const IDS: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+    for (row, line) in rows.iter().enumerate() {
+        let map: String = (0..line.len())
+            .map(|col| match basins.basin_of((row, col)) {
+                Some(id) => IDS[id % IDS.len()] as char,
+                None => '.',
+            })
+            .collect();
+        println!("{}", map);
+    }
+    Ok(())
 }
 
 fn problem1(rows: &[Vec<u8>]) -> u32 {
-    let mut minima = Vec::new();
+    low_points(rows, Connectivity::Four)
+        .into_iter()
+        .map(|(row, col)| rows[row][col] as u32 + 1)
+        .sum()
+}
+
+fn problem2(lines: &[Vec<u8>]) -> u32 {
+    let basins = Basins::new(lines, Connectivity::Four);
+    let mut counts: Vec<_> = basins.basins.iter().map(|b| b.size() as u32).collect();
+    counts.sort_unstable();
+    counts.iter().rev().take(3).product()
+}
+
+/// A cell of the heightmap, by row and then column.
+type Cell = (usize, usize);
+
+/// Which cells count as next to each other.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Connectivity {
+    /// Cells sharing an edge.
+    Four,
+    /// Cells sharing an edge or a corner.
+    Eight,
+}
+
+impl Connectivity {
+    fn neighbours(self, (row, col): Cell, width: usize, height: usize) -> Vec<Cell> {
+        let mut neighbours = Vec::new();
+        for dr in -1..=1isize {
+            for dc in -1..=1isize {
+                if (dr, dc) == (0, 0) || (self == Connectivity::Four && dr != 0 && dc != 0) {
+                    continue;
+                }
+                let r = row.wrapping_add_signed(dr);
+                let c = col.wrapping_add_signed(dc);
+                if r < height && c < width {
+                    neighbours.push((r, c));
+                }
+            }
+        }
+        neighbours
+    }
+}
+
+/// The cells lower than all of their neighbours.
+fn low_points(rows: &[Vec<u8>], connectivity: Connectivity) -> Vec<Cell> {
     let height = rows.len();
     let width = rows[0].len();
+    let mut minima = Vec::new();
     for r in 0..height {
         for c in 0..width {
             let v = rows[r][c];
-            if c > 0 && v >= rows[r][c - 1] {
-                continue;
-            }
-            if c < width - 1 && v >= rows[r][c + 1] {
-                continue;
-            }
-            if r > 0 && v >= rows[r - 1][c] {
-                continue;
-            }
-            if r < height - 1 && v >= rows[r + 1][c] {
-                continue;
+            let neighbours = connectivity.neighbours((r, c), width, height);
+            if neighbours.into_iter().all(|(nr, nc)| v < rows[nr][nc]) {
+                minima.push((r, c));
             }
-            minima.push(v);
         }
     }
-
-    minima.into_iter().map(|m| m as u32 + 1).sum()
+    minima
 }
 
-fn problem2(lines: &[Vec<u8>]) -> u32 {
-    const BARRIER: u8 = 9;
-    let mut locations: Vec<Vec<Location>> = Vec::new();
-    for line in lines {
-        let mapped = line
-            .iter()
-            .map(|v| {
-                if v == &BARRIER {
-                    Location::Barrier
-                } else {
-                    Location::Unvisited
-                }
-            })
-            .collect();
-        locations.push(mapped);
-    }
+/// The regions of the heightmap walled off by height 9.
+struct Basins {
+    basins: Vec<Basin>,
+    /// The basin each cell is in, by row and column.
+    ids: Vec<Vec<Option<usize>>>,
+}
 
-    let height = locations.len();
-    let width = locations[0].len();
+struct Basin {
+    /// The lowest cell of the basin, the first in reading order if several
+    /// are equally low.
+    low_point: Cell,
+    /// Every cell of the basin, in reading order.
+    cells: Vec<Cell>,
+}
 
-    let mut current_basin = 0;
-    for r in 0..height {
-        for c in 0..width {
-            if flood_fill(&mut locations, r, c, width, height, current_basin) {
-                current_basin += 1;
-            }
-        }
+impl Basin {
+    fn size(&self) -> usize {
+        self.cells.len()
     }
+}
 
-    let mut counts = vec![0u32; current_basin];
+impl Basins {
+    fn new(rows: &[Vec<u8>], connectivity: Connectivity) -> Basins {
+        const BARRIER: u8 = 9;
+        let mut locations: Vec<Vec<Location>> = Vec::new();
+        for line in rows {
+            let mapped = line
+                .iter()
+                .map(|v| {
+                    if v == &BARRIER {
+                        Location::Barrier
+                    } else {
+                        Location::Unvisited
+                    }
+                })
+                .collect();
+            locations.push(mapped);
+        }
 
-    for row in locations.into_iter() {
-        for col in row.into_iter() {
-            if let Location::Basin(n) = col {
-                counts[n] += 1;
+        let mut basins = Vec::new();
+        for r in 0..locations.len() {
+            for c in 0..locations[r].len() {
+                let mut cells = flood_fill(&mut locations, (r, c), basins.len(), connectivity);
+                if cells.is_empty() {
+                    continue;
+                }
+                cells.sort_unstable();
+                let low_point = *cells.iter().min_by_key(|(r, c)| rows[*r][*c]).unwrap();
+                basins.push(Basin { low_point, cells });
             }
         }
+
+        let ids = locations
+            .into_iter()
+            .map(|row| {
+                row.into_iter()
+                    .map(|location| match location {
+                        Location::Basin(id) => Some(id),
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .collect();
+        Basins { basins, ids }
     }
 
-    counts.sort_unstable();
-    counts.iter().rev().take(3).product()
+    /// The id of the basin `cell` is in, which indexes `basins`, or `None`
+    /// for the walls between them.
+    fn basin_of(&self, (row, col): Cell) -> Option<usize> {
+        self.ids[row][col]
+    }
 }
 
 #[derive(PartialEq, Eq)]
@@ -94,35 +208,24 @@ enum Location {
     Basin(usize),
 }
 
+/// Marks the unvisited cells reachable from `start` as being in `basin`, and
+/// returns them.
 fn flood_fill(
-    locations: &mut Vec<Vec<Location>>,
-    row: usize,
-    col: usize,
-    width: usize,
-    height: usize,
+    locations: &mut [Vec<Location>],
+    start: Cell,
     basin: usize,
-) -> bool {
-    let mut to_check = vec![(row, col)];
-    let mut filled = false;
+    connectivity: Connectivity,
+) -> Vec<Cell> {
+    let height = locations.len();
+    let width = locations[0].len();
+    let mut to_check = vec![start];
+    let mut filled = Vec::new();
 
     while let Some((row, col)) = to_check.pop() {
         if locations[row][col] == Location::Unvisited {
             locations[row][col] = Location::Basin(basin);
-            filled = true;
-
-            if row > 0 {
-                to_check.push((row - 1, col));
-            }
-            if row < height - 1 {
-                to_check.push((row + 1, col));
-            }
-
-            if col > 0 {
-                to_check.push((row, col - 1));
-            }
-            if col < width - 1 {
-                to_check.push((row, col + 1));
-            }
+            filled.push((row, col));
+            to_check.extend(connectivity.neighbours((row, col), width, height));
         }
     }
 
@@ -142,13 +245,44 @@ mod tests {
 
     #[test]
     fn example1() {
-        let lines = parse_lines(SAMPLE);
+        let lines = parse_lines(SAMPLE).unwrap();
         assert_eq!(problem1(&lines), 15);
     }
 
     #[test]
     fn example2() {
-        let lines = parse_lines(SAMPLE);
+        let lines = parse_lines(SAMPLE).unwrap();
         assert_eq!(problem2(&lines), 1134);
     }
+
+    #[test]
+    fn basins() {
+        let lines = parse_lines(SAMPLE).unwrap();
+        let basins = Basins::new(&lines, Connectivity::Four);
+        let summary: Vec<_> = basins
+            .basins
+            .iter()
+            .map(|b| (b.low_point, b.size()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![((0, 1), 3), ((0, 9), 9), ((2, 2), 14), ((4, 6), 9)]
+        );
+        assert_eq!(basins.basins[0].cells, vec![(0, 0), (0, 1), (1, 0)]);
+        assert_eq!(basins.basin_of((3, 3)), Some(2));
+        assert_eq!(basins.basin_of((0, 2)), None);
+
+        let lowest: Vec<_> = basins.basins.iter().map(|b| b.low_point).collect();
+        assert_eq!(lowest, low_points(&lines, Connectivity::Four));
+
+        // Corners join all four basins into one.
+        let basins = Basins::new(&lines, Connectivity::Eight);
+        assert_eq!(basins.basins.len(), 1);
+        assert_eq!(basins.basins[0].low_point, (0, 9));
+        assert_eq!(basins.basins[0].size(), 35);
+        assert_eq!(
+            low_points(&lines, Connectivity::Eight),
+            vec![(0, 1), (0, 9), (2, 2), (4, 6)]
+        );
+    }
 }
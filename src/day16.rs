@@ -1,88 +1,428 @@
-use std::collections::VecDeque;
-use std::fs;
+use crate::bigint::BigUint;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::ops::RangeInclusive;
 
 const LITERAL_PACKET_TYPE_ID: u32 = 0b100;
 
+/// Type IDs that do not fit in the 3-bit header follow it in a field of this
+/// many bits, marked by a sum whose sub-packets span zero bits. As an empty
+/// sum is always encoded with a sub-packet count, no standard packet reads
+/// as that marker.
+const EXTENDED_TYPE_ID_BITS: u32 = 32;
+
 pub fn run() {
-    let content = fs::read_to_string("input/day16").expect("Unable to read input");
-    let packets = parse(content.trim_end());
+    let file = File::open("input/day16").expect("Unable to read input");
+    let packets = Packets::new(file, Encoding::Hex)
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap_or_else(|e| panic!("Unable to decode input: {}", e));
 
     println!("Day 16");
     println!("  Problem 1: {}", problem1(&packets));
     println!("  Problem 2: {}", problem2(&packets));
 }
 
-fn parse(raw: &str) -> Vec<Packet> {
-    let mut provider = BitProvider::new(raw);
+/// Prints what the transmission `hex` computes, exactly if `big` is set and
+/// in 64 bits otherwise.
+pub fn explain_command(hex: &str, big: bool) -> Result<(), String> {
+    let packets = parse(hex).map_err(|e| format!("Unable to decode transmission: {}", e))?;
+    for packet in packets {
+        explain(&packet, big);
+    }
+    Ok(())
+}
 
-    let mut packets = Vec::new();
-    while !provider.exhausted {
-        let (packet, _) = parse_one(&mut provider);
-        packets.push(packet);
+/// Like `explain_command`, but decodes the transmission in the file at
+/// `path` (or standard input for `-`) as it is read. `encoding` is `hex` (the
+/// default) or `binary`.
+pub fn stream_command(path: &str, encoding: Option<&str>) -> Result<(), String> {
+    let encoding = match encoding {
+        Some("hex") | None => Encoding::Hex,
+        Some("binary") => Encoding::Binary,
+        Some(other) => return Err(format!("Unknown encoding: {}", other)),
+    };
+    let reader: Box<dyn Read> = if path == "-" {
+        Box::new(io::stdin())
+    } else {
+        let file = File::open(path).map_err(|e| format!("Unable to read transmission: {}", e))?;
+        Box::new(file)
+    };
+    for packet in Packets::new(reader, encoding) {
+        let packet = packet.map_err(|e| format!("Unable to decode transmission: {}", e))?;
+        explain(&packet, false);
     }
+    Ok(())
+}
 
-    packets
+fn explain(packet: &Packet, big: bool) {
+    let value = if big {
+        packet.eval_big().map(|v| v.to_string())
+    } else {
+        packet.eval().map(|v| v.to_string())
+    };
+    match value {
+        Ok(v) => println!("{} = {}", packet, v),
+        Err(e) => println!("{} = error: {}", packet, e),
+    }
 }
 
-fn parse_one(provider: &mut BitProvider) -> (Packet, u32) {
-    let version = provider.get(3);
-    let type_id = provider.get(3);
-    let mut consumed = 6;
+/// Prints the transmission computing `expression`. `length_type` is one of
+/// `bits`, `count` or `auto` (the default).
+pub fn compile_command(expression: &str, length_type: Option<&str>) -> Result<(), String> {
+    let length_type = match length_type {
+        Some("bits") => LengthType::BitLength,
+        Some("count") => LengthType::SubpacketCount,
+        Some("auto") | None => LengthType::Auto,
+        Some(other) => return Err(format!("Unknown length type: {}", other)),
+    };
+    let packet =
+        parse_expression(expression).map_err(|e| format!("Unable to parse expression: {}", e))?;
+    let hex =
+        encode(&packet, length_type).map_err(|e| format!("Unable to encode packet: {}", e))?;
+    println!("{}", hex);
+    Ok(())
+}
 
-    if type_id == LITERAL_PACKET_TYPE_ID {
-        let mut value = 0u64;
-        loop {
-            let v = provider.get(5);
-            consumed += 5;
-            value = (value << 4) | (v as u64 & 0b1111);
-            //value.push(v);
+/// Deepest nesting of operator packets accepted by `parse`, so hostile
+/// transmissions cannot overflow the stack.
+const MAX_DEPTH: usize = 1000;
 
-            if (v >> 4) & 1 == 0 {
-                break;
-            }
+fn parse(raw: &str) -> Result<Vec<Packet>, DecodeError> {
+    Packets::new(raw.as_bytes(), Encoding::Hex).collect()
+}
+
+/// Iterator decoding the top-level packets of a transmission one at a time,
+/// as they are read.
+struct Packets<R> {
+    provider: BitProvider<R>,
+    done: bool,
+}
+
+impl<R: Read> Packets<R> {
+    fn new(reader: R, encoding: Encoding) -> Self {
+        Self {
+            provider: BitProvider::new(reader, encoding),
+            done: false,
+        }
+    }
+}
+
+impl<R: Read> Iterator for Packets<R> {
+    type Item = Result<Packet, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
         }
 
-        (
-            Packet {
-                version,
-                r#type: Type::Literal(value),
-            },
-            consumed,
-        )
+        let result = match self.provider.at_end() {
+            Ok(true) => return None,
+            Ok(false) => parse_one(&mut self.provider, 0, None),
+            Err(e) => Err(e),
+        };
+        self.done = result.is_err();
+        Some(result)
+    }
+}
+
+/// Decodes one packet, and if given a `listing` writes a line to it for each
+/// field as it is decoded.
+fn parse_one<R: Read>(
+    provider: &mut BitProvider<R>,
+    depth: usize,
+    mut listing: Option<&mut String>,
+) -> Result<Packet, DecodeError> {
+    let offset = provider.offset;
+    if depth > MAX_DEPTH {
+        return Err(DecodeError::TooDeep { offset });
+    }
+
+    let version = provider.get(3)?;
+    let type_id = provider.get(3)?;
+
+    let r#type = if type_id == LITERAL_PACKET_TYPE_ID {
+        note(&mut listing, offset, depth, || {
+            format!("version {}, type 4 (literal)", version)
+        });
+        Type::literal(parse_literal(provider, depth, &mut listing)?)
     } else {
-        let length_type = provider.get(1);
-        consumed += 1;
+        let type_id = TypeId::try_from(type_id).expect("only literals have type ID 4");
+        note(&mut listing, offset, depth, || {
+            let id = u32::from(&type_id);
+            format!("version {}, type {} ({})", version, id, type_id)
+        });
+        let (type_id, length_type, length) = parse_length(provider, type_id, depth, &mut listing)?;
+
         let mut subpackets = Vec::new();
         if length_type == 0 {
-            let subpacket_length = provider.get(15);
-            consumed += 15;
-            let mut total_subconsumed = 0;
-            while total_subconsumed < subpacket_length {
-                let (subpacket, subconsumed) = parse_one(provider);
-                subpackets.push(subpacket);
-                total_subconsumed += subconsumed;
+            let start = provider.offset;
+            while provider.offset - start < length {
+                subpackets.push(parse_one(provider, depth + 1, listing.as_deref_mut())?);
+            }
+            if provider.offset - start != length {
+                return Err(DecodeError::SubpacketOverrun {
+                    offset: start,
+                    declared: length,
+                    actual: provider.offset - start,
+                });
             }
-            assert_eq!(total_subconsumed, subpacket_length);
-            consumed += total_subconsumed;
         } else {
-            let num_subpackets = provider.get(11);
-            consumed += 11;
-            for _ in 0..num_subpackets {
-                let (subpacket, subconsumed) = parse_one(provider);
-                subpackets.push(subpacket);
-                consumed += subconsumed;
+            for _ in 0..length {
+                subpackets.push(parse_one(provider, depth + 1, listing.as_deref_mut())?);
             }
         }
-        (
-            Packet {
-                version,
-                r#type: Type::Operator {
-                    type_id: type_id.into(),
-                    subpackets,
-                },
-            },
-            consumed,
-        )
+
+        Type::Operator {
+            type_id,
+            subpackets,
+        }
+    };
+
+    Ok(Packet {
+        offset,
+        version,
+        r#type,
+    })
+}
+
+/// Decodes the length type and length of an operator's sub-packets, along
+/// with the extended type ID before them if the header marks one. Returns the
+/// type ID, the length type and the length.
+fn parse_length<R: Read>(
+    provider: &mut BitProvider<R>,
+    mut type_id: TypeId,
+    depth: usize,
+    listing: &mut Option<&mut String>,
+) -> Result<(TypeId, u32, usize), DecodeError> {
+    let mut extended = false;
+    loop {
+        let length_offset = provider.offset;
+        let length_type = provider.get(1)?;
+        if length_type == 1 {
+            let num_subpackets = provider.get(11)? as usize;
+            note(listing, length_offset, depth + 1, || {
+                format!("length type 1, {} sub-packets", num_subpackets)
+            });
+            return Ok((type_id, length_type, num_subpackets));
+        }
+
+        let subpacket_length = provider.get(15)? as usize;
+        if extended || type_id != TypeId::Sum || subpacket_length != 0 {
+            note(listing, length_offset, depth + 1, || {
+                format!("length type 0, {} bits of sub-packets", subpacket_length)
+            });
+            return Ok((type_id, length_type, subpacket_length));
+        }
+
+        note(listing, length_offset, depth + 1, || {
+            "length type 0, 0 bits: extended type ID".to_string()
+        });
+        let id_offset = provider.offset;
+        let id = provider.get(EXTENDED_TYPE_ID_BITS)?;
+        type_id = TypeId::try_from(id).map_err(|_| DecodeError::UnknownTypeId {
+            offset: id_offset,
+            type_id: id,
+        })?;
+        extended = true;
+        note(listing, id_offset, depth + 1, || {
+            format!("type {} ({})", id, type_id)
+        });
+    }
+}
+
+/// Decodes the groups of a literal, listing each one.
+fn parse_literal<R: Read>(
+    provider: &mut BitProvider<R>,
+    depth: usize,
+    listing: &mut Option<&mut String>,
+) -> Result<BigUint, DecodeError> {
+    let mut value = BigUint::default();
+    loop {
+        let group_offset = provider.offset;
+        let v = provider.get(5)?;
+        value.push_nibble(v & 0b1111);
+
+        let last = (v >> 4) & 1 == 0;
+        note(listing, group_offset, depth + 1, || {
+            let group = format!("group {:b} {:04b}", v >> 4, v & 0b1111);
+            if last {
+                format!("{}, value {}", group, value)
+            } else {
+                group
+            }
+        });
+        if last {
+            return Ok(value);
+        }
+    }
+}
+
+/// Adds a line to the listing, if there is one.
+fn note(
+    listing: &mut Option<&mut String>,
+    offset: usize,
+    depth: usize,
+    text: impl FnOnce() -> String,
+) {
+    if let Some(out) = listing.as_deref_mut() {
+        listing_line(out, offset, depth, &text());
+    }
+}
+
+/// Prints an annotated listing of the transmission `hex`, one field per line
+/// with its bit offset.
+pub fn disasm_command(hex: &str) -> Result<(), String> {
+    let mut listing = String::new();
+    let result = disassemble(hex, &mut listing);
+    print!("{}", listing);
+    result.map_err(|e| format!("Unable to decode transmission: {}", e))
+}
+
+/// Writes a listing of the transmission `hex` to `out`, decoding it with
+/// `parse_one` like `parse` does. On error, `out` holds the listing up to the bad field.
+fn disassemble(hex: &str, out: &mut String) -> Result<(), DecodeError> {
+    let mut provider = BitProvider::new(hex.as_bytes(), Encoding::Hex);
+    while !provider.at_end()? {
+        parse_one(&mut provider, 0, Some(out))?;
+    }
+    if provider.pending_zeros > 0 {
+        listing_line(
+            out,
+            provider.offset,
+            0,
+            &format!("padding, {} bits", provider.pending_zeros),
+        );
+    }
+
+    Ok(())
+}
+
+fn listing_line(out: &mut String, offset: usize, depth: usize, text: &str) {
+    out.push_str(&format!("{:>6}  {}{}\n", offset, "  ".repeat(depth), text));
+}
+
+/// Errors found while decoding a transmission. Offsets are in bits from the
+/// start of the transmission.
+#[derive(Debug, PartialEq, Eq)]
+enum DecodeError {
+    InvalidHex {
+        offset: usize,
+        character: char,
+    },
+    Io {
+        offset: usize,
+        kind: io::ErrorKind,
+    },
+    UnexpectedEnd {
+        offset: usize,
+    },
+    UnknownTypeId {
+        offset: usize,
+        type_id: u32,
+    },
+    SubpacketOverrun {
+        offset: usize,
+        declared: usize,
+        actual: usize,
+    },
+    TooDeep {
+        offset: usize,
+    },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::InvalidHex { offset, character } => {
+                write!(f, "invalid hex digit `{}` at bit {}", character, offset)
+            }
+            Self::Io { offset, kind } => {
+                write!(f, "unable to read transmission at bit {}: {}", offset, kind)
+            }
+            Self::UnexpectedEnd { offset } => {
+                write!(
+                    f,
+                    "transmission ends in the middle of a packet at bit {}",
+                    offset
+                )
+            }
+            Self::UnknownTypeId { offset, type_id } => {
+                write!(f, "unknown extended type ID {} at bit {}", type_id, offset)
+            }
+            Self::SubpacketOverrun {
+                offset,
+                declared,
+                actual,
+            } => write!(
+                f,
+                "sub-packets at bit {} span {} bits, but {} were declared",
+                offset, actual, declared
+            ),
+            Self::TooDeep { offset } => write!(
+                f,
+                "packet at bit {} is nested more than {} levels deep",
+                offset, MAX_DEPTH
+            ),
+        }
+    }
+}
+
+/// Errors found while evaluating a packet. Offsets are in bits from the start
+/// of the transmission, and zero for packets that were not decoded.
+#[derive(Debug, PartialEq, Eq)]
+enum EvalError {
+    Arity {
+        offset: usize,
+        type_id: TypeId,
+        subpackets: usize,
+    },
+    Overflow {
+        offset: usize,
+    },
+    OperatorFailed {
+        offset: usize,
+        type_id: TypeId,
+    },
+    UnknownOperator {
+        offset: usize,
+        type_id: TypeId,
+    },
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Arity {
+                offset,
+                type_id,
+                subpackets,
+            } => write!(
+                f,
+                "`{}` packet at bit {} has {} sub-packets",
+                type_id, offset, subpackets
+            ),
+            Self::Overflow { offset } => {
+                write!(
+                    f,
+                    "value of packet at bit {} does not fit in 64 bits",
+                    offset
+                )
+            }
+            Self::OperatorFailed { offset, type_id } => write!(
+                f,
+                "operator for type ID {} failed on packet at bit {}",
+                u32::from(type_id),
+                offset
+            ),
+            Self::UnknownOperator { offset, type_id } => write!(
+                f,
+                "no operator registered for type ID {} of packet at bit {}",
+                u32::from(type_id),
+                offset
+            ),
+        }
     }
 }
 
@@ -104,50 +444,222 @@ fn problem1(packets: &[Packet]) -> u32 {
 }
 
 fn problem2(packets: &[Packet]) -> u64 {
-    packets[0].eval()
+    packets[0]
+        .eval()
+        .unwrap_or_else(|e| panic!("Unable to evaluate input: {}", e))
+}
+
+/// How operator packets record the extent of their sub-packets.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum LengthType {
+    /// Length type 0: the total length of the sub-packets in bits.
+    BitLength,
+    /// Length type 1: the number of sub-packets.
+    SubpacketCount,
+    /// The shorter sub-packet count where it fits, the bit length otherwise.
+    Auto,
+}
+
+fn encode(packet: &Packet, length_type: LengthType) -> Result<String, String> {
+    let mut writer = BitWriter::default();
+    encode_one(packet, length_type, &mut writer)?;
+    Ok(writer.to_hex())
 }
 
-struct BitProvider {
-    bytes: VecDeque<u8>,
-    exhausted: bool,
+fn encode_one(
+    packet: &Packet,
+    length_type: LengthType,
+    writer: &mut BitWriter,
+) -> Result<(), String> {
+    if packet.version >= 1 << 3 {
+        return Err(format!("version {} does not fit in 3 bits", packet.version));
+    }
+    writer.put(packet.version as u64, 3);
+
+    match &packet.r#type {
+        Type::Literal(value) => encode_literal(&BigUint::from(*value), writer),
+        Type::BigLiteral(value) => encode_literal(value, writer),
+        Type::Operator {
+            type_id,
+            subpackets,
+        } => {
+            match type_id {
+                TypeId::Other(id) => {
+                    writer.put(u32::from(&TypeId::Sum) as u64, 3);
+                    writer.put(0, 1);
+                    writer.put(0, 15);
+                    writer.put(*id as u64, EXTENDED_TYPE_ID_BITS);
+                }
+                _ => writer.put(u32::from(type_id) as u64, 3),
+            }
+
+            let mut inner = BitWriter::default();
+            for subpacket in subpackets {
+                encode_one(subpacket, length_type, &mut inner)?;
+            }
+
+            // A zero bit length would mark an extended type ID.
+            let count = subpackets.len();
+            let use_count = count == 0
+                || match length_type {
+                    LengthType::BitLength => false,
+                    LengthType::SubpacketCount => true,
+                    LengthType::Auto => count < 1 << 11,
+                };
+            if use_count {
+                if count >= 1 << 11 {
+                    return Err(format!("{} sub-packets do not fit in 11 bits", count));
+                }
+                writer.put(1, 1);
+                writer.put(count as u64, 11);
+            } else {
+                if inner.len >= 1 << 15 {
+                    return Err(format!(
+                        "{} bits of sub-packets do not fit in 15 bits",
+                        inner.len
+                    ));
+                }
+                writer.put(0, 1);
+                writer.put(inner.len as u64, 15);
+            }
+            writer.append(&inner);
+        }
+    }
+
+    Ok(())
+}
+
+fn encode_literal(value: &BigUint, writer: &mut BitWriter) {
+    writer.put(LITERAL_PACKET_TYPE_ID as u64, 3);
+    let groups = value.bits().div_ceil(4).max(1);
+    for i in (0..groups).rev() {
+        writer.put((i > 0) as u64, 1);
+        writer.put(value.nibble(i) as u64, 4);
+    }
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+impl BitWriter {
+    fn put(&mut self, value: u64, num_bits: u32) {
+        for i in (0..num_bits).rev() {
+            self.push((value >> i) & 1 == 1);
+        }
+    }
+
+    fn push(&mut self, bit: bool) {
+        if self.len.is_multiple_of(8) {
+            self.bytes.push(0);
+        }
+        if bit {
+            *self.bytes.last_mut().unwrap() |= 0x80 >> (self.len % 8);
+        }
+        self.len += 1;
+    }
+
+    fn append(&mut self, other: &BitWriter) {
+        for i in 0..other.len {
+            self.push(other.bytes[i / 8] & (0x80 >> (i % 8)) != 0);
+        }
+    }
+
+    /// Hex digits of the bits written so far, zero-padded to whole bytes.
+    fn to_hex(&self) -> String {
+        self.bytes.iter().map(|b| format!("{:02X}", b)).collect()
+    }
+}
+
+/// How a transmission is written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Encoding {
+    /// Hex digits, ignoring whitespace.
+    Hex,
+    /// Raw bytes.
+    Binary,
+}
+
+/// Reads a transmission bit by bit, only ever holding one byte of it.
+struct BitProvider<R> {
+    bytes: io::Bytes<BufReader<R>>,
+    encoding: Encoding,
+    /// Bits not yet provided from the last byte read, left-aligned, and how
+    /// many there are.
     current: Option<(u8, u32)>,
+    /// Zero bits read past while looking for the end of the transmission,
+    /// which are provided before `current`.
+    pending_zeros: usize,
+    /// Number of bits provided so far.
+    offset: usize,
 }
 
-impl BitProvider {
-    fn new(hex: &str) -> Self {
-        let bytes: VecDeque<u8> = (0..hex.len())
-            .step_by(2)
-            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
-            .collect();
+impl<R: Read> BitProvider<R> {
+    fn new(reader: R, encoding: Encoding) -> Self {
         Self {
-            bytes,
-            exhausted: false,
+            bytes: BufReader::new(reader).bytes(),
+            encoding,
             current: None,
+            pending_zeros: 0,
+            offset: 0,
+        }
+    }
+
+    /// Reads the next bits of the transmission into `current`, returning
+    /// false at its end.
+    fn fill(&mut self) -> Result<bool, DecodeError> {
+        // Bits read but not yet provided, for error offsets.
+        let offset = self.offset + self.pending_zeros;
+        loop {
+            let byte = match self.bytes.next() {
+                Some(Ok(byte)) => byte,
+                Some(Err(e)) => {
+                    return Err(DecodeError::Io {
+                        offset,
+                        kind: e.kind(),
+                    })
+                }
+                None => return Ok(false),
+            };
+
+            self.current = match self.encoding {
+                Encoding::Binary => Some((byte, 8)),
+                Encoding::Hex if byte.is_ascii_whitespace() => continue,
+                Encoding::Hex => {
+                    let digit = (byte as char).to_digit(16).ok_or(DecodeError::InvalidHex {
+                        offset,
+                        character: byte as char,
+                    })?;
+                    Some(((digit as u8) << 4, 4))
+                }
+            };
+            return Ok(true);
         }
     }
 
-    fn get(&mut self, mut num_bits: u32) -> u32 {
+    fn get(&mut self, mut num_bits: u32) -> Result<u32, DecodeError> {
         assert!(num_bits <= u32::BITS);
         let mut value = 0u32;
 
+        let zeros = num_bits.min(self.pending_zeros.try_into().unwrap_or(u32::MAX));
+        self.pending_zeros -= zeros as usize;
+        self.offset += zeros as usize;
+        num_bits -= zeros;
+
         while num_bits > 0 {
-            let (current, remaining) = match self.current {
-                Some((c, r)) => (c, r),
-                None => {
-                    match self.bytes.pop_front() {
-                        Some(b) => (b, 8),
-                        //None => panic!(),
-                        None => {
-                            self.exhausted = true;
-                            (0, 8)
-                        }
-                    }
-                }
-            };
+            if self.current.is_none() && !self.fill()? {
+                return Err(DecodeError::UnexpectedEnd {
+                    offset: self.offset,
+                });
+            }
+            let (current, remaining) = self.current.unwrap();
 
             let to_provide = remaining.min(num_bits);
             value = (value << to_provide) | ((current >> (u8::BITS - to_provide)) as u32);
             num_bits -= to_provide;
+            self.offset += to_provide as usize;
             if remaining > to_provide {
                 self.current = Some((current << to_provide, remaining - to_provide));
             } else {
@@ -155,64 +667,430 @@ impl BitProvider {
             }
         }
 
-        value
+        Ok(value)
+    }
+
+    /// Whether every bit not yet provided is zero, i.e. trailing padding.
+    /// Leading zero bits are read ahead into `pending_zeros` to find out.
+    fn at_end(&mut self) -> Result<bool, DecodeError> {
+        loop {
+            match self.current {
+                Some((0, remaining)) => {
+                    self.pending_zeros += remaining as usize;
+                    self.current = None;
+                }
+                Some((current, remaining)) => {
+                    let zeros = current.leading_zeros();
+                    self.pending_zeros += zeros as usize;
+                    self.current = Some((current << zeros, remaining - zeros));
+                    return Ok(false);
+                }
+                None => {
+                    if !self.fill()? {
+                        return Ok(true);
+                    }
+                }
+            }
+        }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 struct Packet {
+    /// Bit offset of the packet in its transmission, or zero for packets that
+    /// were not decoded.
+    offset: usize,
     version: u32,
     r#type: Type,
 }
 
 impl Packet {
-    fn eval(&self) -> u64 {
+    /// Evaluates in 64 bits with the standard operators, failing on overflow.
+    fn eval(&self) -> Result<u64, EvalError> {
+        self.eval_with(&Registry::standard())
+    }
+
+    /// Evaluates exactly with the standard operators, however large the
+    /// values get.
+    fn eval_big(&self) -> Result<BigUint, EvalError> {
+        self.eval_with(&Registry::standard())
+    }
+
+    fn eval_with<V: Value>(&self, registry: &Registry<V>) -> Result<V, EvalError> {
+        let overflow = EvalError::Overflow {
+            offset: self.offset,
+        };
+        let (type_id, subpackets) = match &self.r#type {
+            Type::Literal(v) => return Ok(V::from(*v)),
+            Type::BigLiteral(v) => return V::from_big(v).ok_or(overflow),
+            Type::Operator {
+                type_id,
+                subpackets,
+            } => (*type_id, subpackets),
+        };
+
+        let operator = registry
+            .operators
+            .get(&type_id)
+            .ok_or(EvalError::UnknownOperator {
+                offset: self.offset,
+                type_id,
+            })?;
+        if !operator.arity.contains(&subpackets.len()) {
+            return Err(EvalError::Arity {
+                offset: self.offset,
+                type_id,
+                subpackets: subpackets.len(),
+            });
+        }
+
+        let values = subpackets
+            .iter()
+            .map(|p| p.eval_with(registry))
+            .collect::<Result<Vec<V>, _>>()?;
+        (operator.eval)(values).ok_or(if operator.standard {
+            overflow
+        } else {
+            EvalError::OperatorFailed {
+                offset: self.offset,
+                type_id,
+            }
+        })
+    }
+}
+
+/// The operators packets are evaluated with, by type ID.
+struct Registry<V> {
+    operators: HashMap<TypeId, Operator<V>>,
+}
+
+struct Operator<V> {
+    /// The numbers of sub-packets the operator can be evaluated with.
+    arity: RangeInclusive<usize>,
+    /// Computes the value from those of the sub-packets, or `None` if it
+    /// cannot.
+    eval: Box<dyn Fn(Vec<V>) -> Option<V>>,
+    /// Whether this is a standard operator, which only fails when the value
+    /// overflows.
+    standard: bool,
+}
+
+impl<V: Value> Registry<V> {
+    fn empty() -> Self {
+        Self {
+            operators: HashMap::new(),
+        }
+    }
+
+    /// The operators of the BITS specification.
+    fn standard() -> Self {
+        let mut registry = Self::empty();
+        registry.register(TypeId::Sum, 0..=usize::MAX, |values| {
+            values.into_iter().try_fold(V::from(0), V::try_add)
+        });
+        registry.register(TypeId::Product, 0..=usize::MAX, |values| {
+            values.into_iter().try_fold(V::from(1), V::try_mul)
+        });
+        registry.register(TypeId::Minimum, 1..=usize::MAX, |values| {
+            values.into_iter().min()
+        });
+        registry.register(TypeId::Maximum, 1..=usize::MAX, |values| {
+            values.into_iter().max()
+        });
+        registry.register(TypeId::GreaterThan, 2..=2, |values| {
+            Some(V::from((values[0] > values[1]).into()))
+        });
+        registry.register(TypeId::LessThan, 2..=2, |values| {
+            Some(V::from((values[0] < values[1]).into()))
+        });
+        registry.register(TypeId::EqualTo, 2..=2, |values| {
+            Some(V::from((values[0] == values[1]).into()))
+        });
+        for operator in registry.operators.values_mut() {
+            operator.standard = true;
+        }
+        registry
+    }
+
+    /// Adds an operator, replacing any already registered for `type_id`.
+    /// `eval` is only called with a number of values within `arity`.
+    fn register(
+        &mut self,
+        type_id: TypeId,
+        arity: RangeInclusive<usize>,
+        eval: impl Fn(Vec<V>) -> Option<V> + 'static,
+    ) {
+        self.operators.insert(
+            type_id,
+            Operator {
+                arity,
+                eval: Box::new(eval),
+                standard: false,
+            },
+        );
+    }
+}
+
+/// Number types packets can be evaluated in.
+trait Value: Ord + From<u64> + 'static {
+    fn from_big(v: &BigUint) -> Option<Self>;
+    fn try_add(self, rhs: Self) -> Option<Self>;
+    fn try_mul(self, rhs: Self) -> Option<Self>;
+}
+
+impl Value for u64 {
+    fn from_big(v: &BigUint) -> Option<Self> {
+        v.to_u64()
+    }
+
+    fn try_add(self, rhs: Self) -> Option<Self> {
+        self.checked_add(rhs)
+    }
+
+    fn try_mul(self, rhs: Self) -> Option<Self> {
+        self.checked_mul(rhs)
+    }
+}
+
+impl Value for BigUint {
+    fn from_big(v: &BigUint) -> Option<Self> {
+        Some(v.clone())
+    }
+
+    fn try_add(self, rhs: Self) -> Option<Self> {
+        Some(self.add(&rhs))
+    }
+
+    fn try_mul(self, rhs: Self) -> Option<Self> {
+        Some(self.mul(&rhs))
+    }
+}
+
+/// Renders the packet as an expression, e.g. `max(3, 7 * (4 + 1)) == 38`.
+/// Versions are not shown.
+impl fmt::Display for Packet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write_expression(f, 0)
+    }
+}
+
+impl Packet {
+    /// Binding strength of the packet's outermost operator. Anything that is
+    /// not written infix binds tightest.
+    fn precedence(&self) -> u8 {
         match &self.r#type {
-            Type::Literal(v) => *v,
             Type::Operator {
                 type_id,
                 subpackets,
-            } => match type_id {
-                TypeId::Sum => subpackets.iter().map(Packet::eval).sum(),
-                TypeId::Product => subpackets.iter().map(Packet::eval).product(),
-                TypeId::Minimum => subpackets.iter().map(Packet::eval).min().unwrap(),
-                TypeId::Maximum => subpackets.iter().map(Packet::eval).max().unwrap(),
-                TypeId::GreaterThan => {
-                    if subpackets[0].eval() > subpackets[1].eval() {
-                        1
-                    } else {
-                        0
-                    }
-                }
-                TypeId::LessThan => {
-                    if subpackets[0].eval() < subpackets[1].eval() {
-                        1
-                    } else {
-                        0
-                    }
-                }
-                TypeId::EqualTo => {
-                    if subpackets[0].eval() == subpackets[1].eval() {
-                        1
-                    } else {
-                        0
-                    }
+            } if subpackets.len() == 2 || (subpackets.len() > 2 && type_id.is_associative()) => {
+                type_id.infix().map_or(4, |(_, p)| p)
+            }
+            _ => 4,
+        }
+    }
+
+    fn write_expression(&self, f: &mut fmt::Formatter, parent_precedence: u8) -> fmt::Result {
+        let (type_id, subpackets) = match &self.r#type {
+            Type::Literal(v) => return write!(f, "{}", v),
+            Type::BigLiteral(v) => return write!(f, "{}", v),
+            Type::Operator {
+                type_id,
+                subpackets,
+            } => (type_id, subpackets),
+        };
+
+        let precedence = self.precedence();
+        if precedence == 4 {
+            write!(f, "{}(", type_id)?;
+            for (i, subpacket) in subpackets.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
                 }
+                subpacket.write_expression(f, 0)?;
+            }
+            return write!(f, ")");
+        }
+
+        let (symbol, _) = type_id.infix().unwrap();
+        if precedence <= parent_precedence {
+            write!(f, "(")?;
+        }
+        for (i, subpacket) in subpackets.iter().enumerate() {
+            if i > 0 {
+                write!(f, " {} ", symbol)?;
+            }
+            subpacket.write_expression(f, precedence)?;
+        }
+        if precedence <= parent_precedence {
+            write!(f, ")")?;
+        }
+        Ok(())
+    }
+}
+
+/// Compiles an expression in the format written by `Packet`'s `Display` into
+/// a packet tree with all versions set to zero.
+fn parse_expression(s: &str) -> Result<Packet, String> {
+    let tokens = tokenize(s)?;
+    let mut parser = ExpressionParser { tokens, pos: 0 };
+    let packet = parser.comparison()?;
+    match parser.tokens.get(parser.pos) {
+        Some((offset, token)) => Err(format!("unexpected `{}` at offset {}", token, offset)),
+        None => Ok(packet),
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<(usize, String)>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = s.char_indices().peekable();
+    while let Some((offset, c)) = chars.next() {
+        let mut token = c.to_string();
+        if c.is_ascii_alphanumeric() {
+            while let Some((_, c)) = chars.next_if(|(_, c)| c.is_ascii_alphanumeric()) {
+                token.push(c);
+            }
+        } else if c == '=' {
+            match chars.next() {
+                Some((_, '=')) => token.push('='),
+                _ => return Err(format!("expected `==` at offset {}", offset)),
+            }
+        } else if c.is_whitespace() {
+            continue;
+        } else if !"+*<>(),".contains(c) {
+            return Err(format!("unexpected `{}` at offset {}", c, offset));
+        }
+        tokens.push((offset, token));
+    }
+    Ok(tokens)
+}
+
+struct ExpressionParser {
+    tokens: Vec<(usize, String)>,
+    pos: usize,
+}
+
+impl ExpressionParser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(|(_, t)| t.as_str())
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        match self.tokens.get(self.pos) {
+            Some((_, t)) if t == expected => {
+                self.pos += 1;
+                Ok(())
+            }
+            Some((offset, t)) => Err(format!(
+                "expected `{}` at offset {}, found `{}`",
+                expected, offset, t
+            )),
+            None => Err(format!("expected `{}` at end of input", expected)),
+        }
+    }
+
+    fn operator(type_id: TypeId, subpackets: Vec<Packet>) -> Packet {
+        Packet {
+            offset: 0,
+            version: 0,
+            r#type: Type::Operator {
+                type_id,
+                subpackets,
             },
         }
     }
+
+    /// Comparisons do not chain, so at most one is consumed.
+    fn comparison(&mut self) -> Result<Packet, String> {
+        let lhs = self.infix(TypeId::Sum)?;
+        let type_id = match self.peek() {
+            Some(">") => TypeId::GreaterThan,
+            Some("<") => TypeId::LessThan,
+            Some("==") => TypeId::EqualTo,
+            _ => return Ok(lhs),
+        };
+        self.pos += 1;
+        let rhs = self.infix(TypeId::Sum)?;
+        Ok(Self::operator(type_id, vec![lhs, rhs]))
+    }
+
+    /// Parses a chain of `+` or `*`, which become a single n-ary packet.
+    fn infix(&mut self, type_id: TypeId) -> Result<Packet, String> {
+        let (symbol, _) = type_id.infix().unwrap();
+        let operand = |parser: &mut Self| match type_id {
+            TypeId::Sum => parser.infix(TypeId::Product),
+            _ => parser.atom(),
+        };
+
+        let mut operands = vec![operand(self)?];
+        while self.peek() == Some(symbol) {
+            self.pos += 1;
+            operands.push(operand(self)?);
+        }
+
+        if operands.len() == 1 {
+            Ok(operands.pop().unwrap())
+        } else {
+            Ok(Self::operator(type_id, operands))
+        }
+    }
+
+    fn atom(&mut self) -> Result<Packet, String> {
+        let (offset, token) = match self.tokens.get(self.pos) {
+            Some((offset, token)) => (*offset, token.clone()),
+            None => return Err("unexpected end of input".to_string()),
+        };
+        self.pos += 1;
+
+        if token == "(" {
+            let packet = self.comparison()?;
+            self.expect(")")?;
+            return Ok(packet);
+        }
+        if let Ok(v) = token.parse() {
+            return Ok(Packet {
+                offset: 0,
+                version: 0,
+                r#type: Type::literal(v),
+            });
+        }
+
+        let type_id = TypeId::from_function_name(&token)
+            .ok_or_else(|| format!("unexpected `{}` at offset {}", token, offset))?;
+        self.expect("(")?;
+        let mut subpackets = Vec::new();
+        if self.peek() != Some(")") {
+            subpackets.push(self.comparison()?);
+            while self.peek() == Some(",") {
+                self.pos += 1;
+                subpackets.push(self.comparison()?);
+            }
+        }
+        self.expect(")")?;
+        Ok(Self::operator(type_id, subpackets))
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 enum Type {
     Literal(u64),
+    /// A literal too large for a `u64`.
+    BigLiteral(BigUint),
     Operator {
         type_id: TypeId,
         subpackets: Vec<Packet>,
     },
 }
 
-#[derive(Debug)]
+impl Type {
+    fn literal(value: BigUint) -> Self {
+        match value.to_u64() {
+            Some(v) => Self::Literal(v),
+            None => Self::BigLiteral(value),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 enum TypeId {
     Sum,
     Product,
@@ -221,19 +1099,93 @@ enum TypeId {
     GreaterThan,
     LessThan,
     EqualTo,
+    /// A type ID of 8 or more, for custom operators. It is encoded after the
+    /// 3-bit header, see `EXTENDED_TYPE_ID_BITS`.
+    Other(u32),
 }
 
-impl From<u32> for TypeId {
-    fn from(v: u32) -> Self {
+impl TypeId {
+    fn from_function_name(name: &str) -> Option<Self> {
+        if let Some(id) = name.strip_prefix("op") {
+            return match id.parse::<u32>().map(Self::try_from) {
+                Ok(Ok(t @ Self::Other(_))) => Some(t),
+                _ => None,
+            };
+        }
+        [
+            Self::Sum,
+            Self::Product,
+            Self::Minimum,
+            Self::Maximum,
+            Self::GreaterThan,
+            Self::LessThan,
+            Self::EqualTo,
+        ]
+        .into_iter()
+        .find(|t| t.to_string() == name)
+    }
+
+    /// The infix symbol and its precedence, for operators that have one.
+    fn infix(&self) -> Option<(&'static str, u8)> {
+        match self {
+            Self::Sum => Some(("+", 2)),
+            Self::Product => Some(("*", 3)),
+            Self::Minimum | Self::Maximum | Self::Other(_) => None,
+            Self::GreaterThan => Some((">", 1)),
+            Self::LessThan => Some(("<", 1)),
+            Self::EqualTo => Some(("==", 1)),
+        }
+    }
+
+    fn is_associative(&self) -> bool {
+        matches!(self, Self::Sum | Self::Product)
+    }
+}
+
+/// The function name the operator is written with in expressions.
+impl fmt::Display for TypeId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Sum => write!(f, "sum"),
+            Self::Product => write!(f, "product"),
+            Self::Minimum => write!(f, "min"),
+            Self::Maximum => write!(f, "max"),
+            Self::GreaterThan => write!(f, "gt"),
+            Self::LessThan => write!(f, "lt"),
+            Self::EqualTo => write!(f, "eq"),
+            Self::Other(id) => write!(f, "op{}", id),
+        }
+    }
+}
+
+impl From<&TypeId> for u32 {
+    fn from(v: &TypeId) -> Self {
+        match v {
+            TypeId::Sum => 0,
+            TypeId::Product => 1,
+            TypeId::Minimum => 2,
+            TypeId::Maximum => 3,
+            TypeId::GreaterThan => 5,
+            TypeId::LessThan => 6,
+            TypeId::EqualTo => 7,
+            TypeId::Other(id) => *id,
+        }
+    }
+}
+
+impl TryFrom<u32> for TypeId {
+    type Error = ();
+    fn try_from(v: u32) -> Result<Self, ()> {
         match v {
-            0 => Self::Sum,
-            1 => Self::Product,
-            2 => Self::Minimum,
-            3 => Self::Maximum,
-            5 => Self::GreaterThan,
-            6 => Self::LessThan,
-            7 => Self::EqualTo,
-            _ => panic!(),
+            0 => Ok(Self::Sum),
+            1 => Ok(Self::Product),
+            2 => Ok(Self::Minimum),
+            3 => Ok(Self::Maximum),
+            5 => Ok(Self::GreaterThan),
+            6 => Ok(Self::LessThan),
+            7 => Ok(Self::EqualTo),
+            LITERAL_PACKET_TYPE_ID => Err(()),
+            _ => Ok(Self::Other(v)),
         }
     }
 }
@@ -241,97 +1193,536 @@ impl From<u32> for TypeId {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::rng::Rng;
 
     #[test]
     fn provider1() {
-        let mut provider = BitProvider::new("D2FE28");
-        assert_eq!(provider.get(3), 0b110);
-        assert_eq!(provider.get(3), 0b100);
-        assert_eq!(provider.get(5), 0b10111);
-        assert_eq!(provider.get(5), 0b11110);
-        assert_eq!(provider.get(5), 0b00101);
+        let mut provider = BitProvider::new("D2FE28".as_bytes(), Encoding::Hex);
+        assert_eq!(provider.get(3), Ok(0b110));
+        assert_eq!(provider.get(3), Ok(0b100));
+        assert_eq!(provider.get(5), Ok(0b10111));
+        assert_eq!(provider.get(5), Ok(0b11110));
+        assert_eq!(provider.get(5), Ok(0b00101));
     }
 
     #[test]
     fn provider2() {
-        let mut provider = BitProvider::new("38006F45291200");
-        assert_eq!(provider.get(3), 0b001);
-        assert_eq!(provider.get(3), 0b110);
-        assert_eq!(provider.get(1), 0b0);
-        assert_eq!(provider.get(15), 0b000000000011011);
-        assert_eq!(provider.get(11), 0b11010001010);
-        assert_eq!(provider.get(16), 0b0101001000100100);
+        let mut provider = BitProvider::new("38006F45291200".as_bytes(), Encoding::Hex);
+        assert_eq!(provider.get(3), Ok(0b001));
+        assert_eq!(provider.get(3), Ok(0b110));
+        assert_eq!(provider.get(1), Ok(0b0));
+        assert_eq!(provider.get(15), Ok(0b000000000011011));
+        assert_eq!(provider.get(11), Ok(0b11010001010));
+        assert_eq!(provider.get(16), Ok(0b0101001000100100));
     }
 
     #[test]
     fn example1_sample1() {
-        let packets = parse("8A004A801A8002F478");
+        let packets = parse("8A004A801A8002F478").unwrap();
         assert_eq!(problem1(&packets), 16);
     }
 
     #[test]
     fn example1_sample2() {
-        let packets = parse("620080001611562C8802118E34");
+        let packets = parse("620080001611562C8802118E34").unwrap();
         assert_eq!(problem1(&packets), 12);
     }
 
     #[test]
     fn example1_sample3() {
-        let packets = parse("C0015000016115A2E0802F182340");
+        let packets = parse("C0015000016115A2E0802F182340").unwrap();
         assert_eq!(problem1(&packets), 23);
     }
 
     #[test]
     fn example1_sample4() {
-        let packets = parse("A0016C880162017C3686B18A3D4780");
+        let packets = parse("A0016C880162017C3686B18A3D4780").unwrap();
         assert_eq!(problem1(&packets), 31);
     }
 
     #[test]
     fn example2_sample1() {
-        let packets = parse("C200B40A82");
+        let packets = parse("C200B40A82").unwrap();
         assert_eq!(problem2(&packets), 3);
     }
 
     #[test]
     fn example2_sample2() {
-        let packets = parse("04005AC33890");
+        let packets = parse("04005AC33890").unwrap();
         assert_eq!(problem2(&packets), 54);
     }
 
     #[test]
     fn example2_sample3() {
-        let packets = parse("880086C3E88112");
+        let packets = parse("880086C3E88112").unwrap();
         assert_eq!(problem2(&packets), 7);
     }
 
     #[test]
     fn example2_sample4() {
-        let packets = parse("CE00C43D881120");
+        let packets = parse("CE00C43D881120").unwrap();
         assert_eq!(problem2(&packets), 9);
     }
 
     #[test]
     fn example2_sample5() {
-        let packets = parse("D8005AC2A8F0");
+        let packets = parse("D8005AC2A8F0").unwrap();
         assert_eq!(problem2(&packets), 1);
     }
 
     #[test]
     fn example2_sample6() {
-        let packets = parse("F600BC2D8F");
+        let packets = parse("F600BC2D8F").unwrap();
         assert_eq!(problem2(&packets), 0);
     }
 
     #[test]
     fn example2_sample7() {
-        let packets = parse("9C005AC2F8F0");
+        let packets = parse("9C005AC2F8F0").unwrap();
         assert_eq!(problem2(&packets), 0);
     }
 
     #[test]
     fn example2_sample8() {
-        let packets = parse("9C0141080250320F1802104A08");
+        let packets = parse("9C0141080250320F1802104A08").unwrap();
         assert_eq!(problem2(&packets), 1);
     }
+
+    /// Decodes `hex` with all offsets zeroed, to compare against constructed
+    /// packets.
+    fn parse_without_offsets(hex: &str) -> Vec<Packet> {
+        fn clear(packet: &mut Packet) {
+            packet.offset = 0;
+            if let Type::Operator { subpackets, .. } = &mut packet.r#type {
+                subpackets.iter_mut().for_each(clear);
+            }
+        }
+
+        let mut packets = parse(hex).unwrap();
+        packets.iter_mut().for_each(clear);
+        packets
+    }
+
+    /// Builds a pseudo-random packet tree.
+    fn random_packet(rng: &mut Rng, depth: u32) -> Packet {
+        let version = rng.below(8) as u32;
+        let r#type = if depth == 0 || rng.below(3) == 0 {
+            Type::Literal(rng.below(u64::MAX) >> rng.below(64))
+        } else {
+            let num_subpackets = rng.below(4) + 1;
+            Type::Operator {
+                type_id: TypeId::try_from([0, 1, 2, 3, 5, 6, 7, 9, 70000][rng.below(9) as usize])
+                    .unwrap(),
+                subpackets: (0..num_subpackets)
+                    .map(|_| random_packet(rng, depth - 1))
+                    .collect(),
+            }
+        };
+        Packet {
+            offset: 0,
+            version,
+            r#type,
+        }
+    }
+
+    #[test]
+    fn encode_literal() {
+        let packet = Packet {
+            offset: 0,
+            version: 6,
+            r#type: Type::Literal(2021),
+        };
+        assert_eq!(encode(&packet, LengthType::Auto), Ok("D2FE28".to_string()));
+    }
+
+    #[test]
+    fn encode_operator() {
+        let packets = parse("38006F45291200").unwrap();
+        assert_eq!(
+            encode(&packets[0], LengthType::BitLength),
+            Ok("38006F45291200".to_string())
+        );
+        let packets = parse("EE00D40C823060").unwrap();
+        assert_eq!(
+            encode(&packets[0], LengthType::SubpacketCount),
+            Ok("EE00D40C823060".to_string())
+        );
+    }
+
+    #[test]
+    fn encode_too_many_subpackets() {
+        let packet = Packet {
+            offset: 0,
+            version: 0,
+            r#type: Type::Operator {
+                type_id: TypeId::Sum,
+                subpackets: (0..2048)
+                    .map(|_| Packet {
+                        offset: 0,
+                        version: 0,
+                        r#type: Type::Literal(1),
+                    })
+                    .collect(),
+            },
+        };
+        assert!(encode(&packet, LengthType::SubpacketCount).is_err());
+        let hex = encode(&packet, LengthType::Auto).unwrap();
+        assert_eq!(parse_without_offsets(&hex), vec![packet]);
+    }
+
+    #[test]
+    fn encode_empty_sum() {
+        let packet = Packet {
+            offset: 0,
+            version: 0,
+            r#type: Type::Operator {
+                type_id: TypeId::Sum,
+                subpackets: Vec::new(),
+            },
+        };
+        let hex = encode(&packet, LengthType::BitLength).unwrap();
+        assert_eq!(hex, "020000");
+        assert_eq!(parse_without_offsets(&hex), vec![packet]);
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut rng = Rng::new(16);
+        for _ in 0..500 {
+            let packet = random_packet(&mut rng, 4);
+            for length_type in [
+                LengthType::BitLength,
+                LengthType::SubpacketCount,
+                LengthType::Auto,
+            ] {
+                let hex = encode(&packet, length_type).unwrap();
+                assert_eq!(hex.len() % 2, 0);
+                assert_eq!(
+                    parse_without_offsets(&hex),
+                    std::slice::from_ref(&packet),
+                    "{}",
+                    hex
+                );
+            }
+        }
+    }
+
+    /// Zeroes all versions, since expressions do not carry them.
+    fn strip_versions(packet: &mut Packet) {
+        packet.version = 0;
+        if let Type::Operator { subpackets, .. } = &mut packet.r#type {
+            subpackets.iter_mut().for_each(strip_versions);
+        }
+    }
+
+    #[test]
+    fn display_expression() {
+        let packet = parse_expression("max(3, 7 * (4 + 1)) == 38").unwrap();
+        assert_eq!(packet.to_string(), "max(3, 7 * (4 + 1)) == 38");
+        assert_eq!(packet.eval(), Ok(0));
+
+        assert_eq!(
+            parse("9C0141080250320F1802104A08").unwrap()[0].to_string(),
+            "1 + 3 == 2 * 2"
+        );
+        assert_eq!(
+            parse("880086C3E88112").unwrap()[0].to_string(),
+            "min(7, 8, 9)"
+        );
+        assert_eq!(parse_expression("sum(5)").unwrap().to_string(), "sum(5)");
+        assert_eq!(
+            parse_expression("(1 + 2) + 3").unwrap().to_string(),
+            "(1 + 2) + 3"
+        );
+        assert_eq!(
+            parse_expression("gt(1, 2, 3)").unwrap().to_string(),
+            "gt(1, 2, 3)"
+        );
+        assert_eq!(
+            parse_expression("(1 < 2) == (2 > 1)").unwrap().to_string(),
+            "(1 < 2) == (2 > 1)"
+        );
+    }
+
+    #[test]
+    fn compile_expression() {
+        let packet = parse_expression("1 + 3 == 2 * 2").unwrap();
+        let hex = encode(&packet, LengthType::Auto).unwrap();
+        assert_eq!(problem2(&parse(&hex).unwrap()), 1);
+        assert_eq!(parse_without_offsets(&hex), vec![packet]);
+    }
+
+    #[test]
+    fn expression_errors() {
+        assert!(parse_expression("1 +").is_err());
+        assert!(parse_expression("1 < 2 < 3").is_err());
+        assert!(parse_expression("avg(1, 2)").is_err());
+        assert!(parse_expression("(1 + 2").is_err());
+        assert!(parse_expression("1 = 2").is_err());
+        assert!(parse_expression("1 - 2").is_err());
+    }
+
+    #[test]
+    fn expression_round_trip() {
+        let mut rng = Rng::new(31);
+        for _ in 0..500 {
+            let mut packet = random_packet(&mut rng, 4);
+            strip_versions(&mut packet);
+            let expression = packet.to_string();
+            assert_eq!(parse_expression(&expression), Ok(packet), "{}", expression);
+        }
+    }
+
+    #[test]
+    fn decode_errors() {
+        assert_eq!(
+            parse("D2FG28"),
+            Err(DecodeError::InvalidHex {
+                offset: 12,
+                character: 'G'
+            })
+        );
+        assert_eq!(
+            parse("D2FE2"),
+            Err(DecodeError::UnexpectedEnd { offset: 20 })
+        );
+        assert_eq!(
+            parse("38006F4529"),
+            Err(DecodeError::UnexpectedEnd { offset: 40 })
+        );
+
+        let mut writer = BitWriter::default();
+        writer.put(0, 6);
+        writer.put(0, 1);
+        writer.put(10, 15);
+        writer.put(0b00010000001, 11);
+        assert_eq!(
+            parse(&writer.to_hex()),
+            Err(DecodeError::SubpacketOverrun {
+                offset: 22,
+                declared: 10,
+                actual: 11
+            })
+        );
+
+        let mut writer = BitWriter::default();
+        writer.put(0, 6);
+        writer.put(0, 16);
+        writer.put(LITERAL_PACKET_TYPE_ID as u64, EXTENDED_TYPE_ID_BITS);
+        assert_eq!(
+            parse(&writer.to_hex()),
+            Err(DecodeError::UnknownTypeId {
+                offset: 22,
+                type_id: 4
+            })
+        );
+
+        let mut writer = BitWriter::default();
+        for _ in 0..=MAX_DEPTH + 1 {
+            writer.put(0, 6);
+            writer.put(1, 1);
+            writer.put(1, 11);
+        }
+        writer.put(0b00010000001, 11);
+        assert_eq!(
+            parse(&writer.to_hex()),
+            Err(DecodeError::TooDeep {
+                offset: (MAX_DEPTH + 1) * 18
+            })
+        );
+    }
+
+    #[test]
+    fn decode_odd_length() {
+        let packets = parse("102").unwrap();
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].r#type, Type::Literal(1));
+    }
+
+    #[test]
+    fn eval_errors() {
+        let packet = parse_expression("1 + gt(1, 2, 3)").unwrap();
+        let hex = encode(&packet, LengthType::Auto).unwrap();
+        assert_eq!(
+            parse(&hex).unwrap()[0].eval(),
+            Err(EvalError::Arity {
+                offset: 29,
+                type_id: TypeId::GreaterThan,
+                subpackets: 3
+            })
+        );
+        assert!(parse_expression("max()").unwrap().eval().is_err());
+    }
+
+    #[test]
+    fn big_literal() {
+        let packet = parse_expression("18446744073709551616").unwrap();
+        let hex = encode(&packet, LengthType::Auto).unwrap();
+        let packets = parse(&hex).unwrap();
+        assert_eq!(packets[0].eval(), Err(EvalError::Overflow { offset: 0 }));
+        assert_eq!(
+            packets[0].eval_big().unwrap().to_string(),
+            "18446744073709551616"
+        );
+        assert_eq!(packets[0].to_string(), "18446744073709551616");
+    }
+
+    #[test]
+    fn overflow() {
+        for (expression, exact) in [
+            ("18446744073709551615 + 1", "18446744073709551616"),
+            ("1099511627776 * 1099511627776", "1208925819614629174706176"),
+            (
+                "max(3, 18446744073709551615 * 18446744073709551615)",
+                "340282366920938463426481119284349108225",
+            ),
+        ] {
+            let packet = parse_expression(expression).unwrap();
+            assert!(
+                matches!(packet.eval(), Err(EvalError::Overflow { .. })),
+                "{}",
+                expression
+            );
+            assert_eq!(packet.eval_big().unwrap().to_string(), exact);
+        }
+
+        let packet = parse_expression("2 * 3 + 1000000000000000000000 > 10").unwrap();
+        assert_eq!(packet.eval_big(), Ok(BigUint::from(1)));
+        assert_eq!(parse_expression("2 * 21").unwrap().eval(), Ok(42));
+    }
+
+    #[test]
+    fn disassemble_listing() {
+        let mut listing = String::new();
+        disassemble("38006F45291200", &mut listing).unwrap();
+        assert_eq!(
+            listing,
+            "     0  version 1, type 6 (lt)\n\
+             \x20    6    length type 0, 27 bits of sub-packets\n\
+             \x20   22    version 6, type 4 (literal)\n\
+             \x20   28      group 0 1010, value 10\n\
+             \x20   33    version 2, type 4 (literal)\n\
+             \x20   39      group 1 0001\n\
+             \x20   44      group 0 0100, value 20\n\
+             \x20   49  padding, 7 bits\n"
+        );
+
+        let mut listing = String::new();
+        let result = disassemble("D2FE", &mut listing);
+        assert_eq!(result, Err(DecodeError::UnexpectedEnd { offset: 16 }));
+        assert_eq!(
+            listing,
+            "     0  version 6, type 4 (literal)\n\
+             \x20    6    group 1 0111\n\
+             \x20   11    group 1 1110\n"
+        );
+
+        let packet = parse_expression("op9(3)").unwrap();
+        let hex = encode(&packet, LengthType::SubpacketCount).unwrap();
+        let mut listing = String::new();
+        disassemble(&hex, &mut listing).unwrap();
+        assert_eq!(
+            listing,
+            "     0  version 0, type 0 (sum)\n\
+             \x20    6    length type 0, 0 bits: extended type ID\n\
+             \x20   22    type 9 (op9)\n\
+             \x20   54    length type 1, 1 sub-packets\n\
+             \x20   66    version 0, type 4 (literal)\n\
+             \x20   72      group 0 0011, value 3\n\
+             \x20   77  padding, 3 bits\n"
+        );
+    }
+
+    #[test]
+    fn stream_packets() {
+        let mut writer = BitWriter::default();
+        for expression in ["1 + 2", "6 * 9", "min(7, 8, 9)"] {
+            let packet = parse_expression(expression).unwrap();
+            encode_one(&packet, LengthType::Auto, &mut writer).unwrap();
+        }
+        let hex = writer.to_hex();
+        let spaced = format!("{} {}\n", &hex[..5], &hex[5..]);
+        let values = Packets::new(spaced.as_bytes(), Encoding::Hex)
+            .map(|p| p.unwrap().eval().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(values, vec![3, 54, 7]);
+
+        let packets = Packets::new(&writer.bytes[..], Encoding::Binary)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(packets.len(), 3);
+        assert_eq!(packets[2].to_string(), "min(7, 8, 9)");
+
+        // A packet starting with zero bits is not mistaken for padding.
+        let hex = encode(&parse_expression("0 + 1").unwrap(), LengthType::Auto).unwrap();
+        assert_eq!(parse(&hex).unwrap()[0].eval(), Ok(1));
+
+        let mut packets = Packets::new("D2FE28D2".as_bytes(), Encoding::Hex);
+        assert_eq!(packets.next().unwrap().unwrap().eval(), Ok(2021));
+        assert_eq!(
+            packets.next(),
+            Some(Err(DecodeError::UnexpectedEnd { offset: 32 }))
+        );
+        assert_eq!(packets.next(), None);
+    }
+
+    #[test]
+    fn custom_operators() {
+        let mut registry = Registry::<u64>::standard();
+        registry.register(TypeId::GreaterThan, 1..=usize::MAX, |values| {
+            Some(values.iter().all(|v| *v != 0).into())
+        });
+        registry.register(TypeId::Minimum, 1..=1, |values| values[0].checked_neg());
+        registry.register(TypeId::Maximum, 2..=2, |values| Some(values[0] ^ values[1]));
+
+        let packet = parse_expression("gt(1, 2, 3)").unwrap();
+        assert_eq!(packet.eval_with(&registry), Ok(1));
+        assert_eq!(
+            packet.eval(),
+            Err(EvalError::Arity {
+                offset: 0,
+                type_id: TypeId::GreaterThan,
+                subpackets: 3
+            })
+        );
+
+        let packet = parse_expression("max(12, min(0)) + 1").unwrap();
+        assert_eq!(packet.eval_with(&registry), Ok(13));
+        assert_eq!(
+            parse_expression("min(1)").unwrap().eval_with(&registry),
+            Err(EvalError::OperatorFailed {
+                offset: 0,
+                type_id: TypeId::Minimum
+            })
+        );
+        assert_eq!(
+            packet.eval_with(&Registry::<u64>::empty()),
+            Err(EvalError::UnknownOperator {
+                offset: 0,
+                type_id: TypeId::Sum
+            })
+        );
+
+        // Redefined operators are still encoded in the 3-bit header.
+        let hex = encode(&packet, LengthType::Auto).unwrap();
+        assert_eq!(parse(&hex).unwrap()[0].eval_with(&registry), Ok(13));
+
+        registry.register(TypeId::Other(9), 1..=1, |values| Some(values[0] * 2));
+        let packet = parse_expression("op9(20) + 2").unwrap();
+        assert_eq!(packet.to_string(), "op9(20) + 2");
+        assert_eq!(packet.eval_with(&registry), Ok(42));
+        assert_eq!(
+            packet.eval(),
+            Err(EvalError::UnknownOperator {
+                offset: 0,
+                type_id: TypeId::Other(9)
+            })
+        );
+        for length_type in [LengthType::BitLength, LengthType::SubpacketCount] {
+            let hex = encode(&packet, length_type).unwrap();
+            assert_eq!(parse(&hex).unwrap()[0].eval_with(&registry), Ok(42));
+        }
+        assert!(parse_expression("op4(1)").is_err());
+        assert!(parse_expression("op3(1)").is_err());
+    }
 }
@@ -1,121 +1,564 @@
+use crate::map::{check_map_size, MapTooLarge};
+use std::fmt;
 use std::fs;
 use std::ops::RangeInclusive;
 
 pub fn run() {
     let content = fs::read_to_string("input/day17").expect("Unable to read input");
-    let (x, y) = parse(content.trim_end());
+    let target = parse(content.trim_end()).expect("Invalid input");
+    let physics = Physics::default();
 
     println!("Day 17");
-    println!("  Problem 1: {}", problem1(x.clone(), y.clone()));
-    println!("  Problem 2: {}", problem2(x, y));
+    println!("  Problem 1: {}", problem1(&physics, &target));
+    println!("  Problem 2: {}", problem2(&physics, &target));
 }
 
-fn parse(s: &str) -> (RangeInclusive<i32>, RangeInclusive<i32>) {
-    let s = s.strip_prefix("target area: ").unwrap();
-    let mut parts = s.split(", ");
+/// Prints the path of a probe launched with `velocity` at the target described
+/// by `target` (as in the puzzle input), or writes it as a PPM image to
+/// `image_path`.
+pub fn trace_command(
+    target: &str,
+    velocity: (&str, &str),
+    image_path: Option<&str>,
+) -> Result<(), String> {
+    let target = parse(target).ok_or_else(|| format!("Invalid target: {}", target))?;
+    let (dx, dy) = velocity;
+    let velocity = (
+        dx.parse().map_err(|_| format!("Invalid dx: {}", dx))?,
+        dy.parse().map_err(|_| format!("Invalid dy: {}", dy))?,
+        0,
+    );
+    let trajectory = trajectory(&Physics::default(), velocity, &target);
 
-    let mut x_parts = parts
-        .next()
-        .unwrap()
-        .strip_prefix("x=")
-        .unwrap()
-        .split("..");
-    let mut y_parts = parts
-        .next()
-        .unwrap()
-        .strip_prefix("y=")
-        .unwrap()
-        .split("..");
-
-    let min_x = x_parts.next().unwrap().parse().unwrap();
-    let max_x = x_parts.next().unwrap().parse().unwrap();
-    let min_y = y_parts.next().unwrap().parse().unwrap();
-    let max_y = y_parts.next().unwrap().parse().unwrap();
-
-    (min_x..=max_x, min_y..=max_y)
-}
-
-fn problem1(target_x: RangeInclusive<i32>, target_y: RangeInclusive<i32>) -> i32 {
-    let mut min_dx = 1;
-    let max_dx = *target_x.end();
-    while !target_x.contains(&sum_to(min_dx)) {
-        min_dx += 1;
-    }
-
-    let mut overall_max_y = i32::MIN;
-    for original_dx in min_dx..=max_dx {
-        for original_dy in 0..1000 {
-            let mut dx = original_dx;
-            let mut dy = original_dy;
-            let mut x = 0;
-            let mut y = 0;
-            let mut max_y = 0;
-            while &x <= target_x.end() && &y >= target_y.start() {
-                x += dx;
-                y += dy;
-
-                if y > max_y {
-                    max_y = y;
-                }
+    match image_path {
+        Some(path) => {
+            let image = render_ppm(&trajectory, &target).map_err(|e| e.to_string())?;
+            fs::write(path, image).map_err(|e| format!("Unable to write image: {}", e))?;
+        }
+        None => print!(
+            "{}",
+            render(&trajectory, &target).map_err(|e| e.to_string())?
+        ),
+    }
+    match trajectory.hit {
+        Some(step) => println!("Hits the target after step {}", step),
+        None => println!("Misses the target"),
+    }
+    Ok(())
+}
+
+/// Positions and velocities, as `(x, y, z)` with `y` pointing up.
+type Vector = (i64, i64, i64);
+
+/// The area the probe should end up in.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Target {
+    x: RangeInclusive<i64>,
+    y: RangeInclusive<i64>,
+    /// The depth of a box target. Without it, the probe's `z` coordinate is
+    /// not considered.
+    z: Option<RangeInclusive<i64>>,
+}
+
+impl Target {
+    fn contains(&self, (x, y, z): Vector) -> bool {
+        self.x.contains(&x) && self.y.contains(&y) && self.z.as_ref().is_none_or(|r| r.contains(&z))
+    }
+}
+
+/// How the probe's velocity changes each step.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Physics {
+    /// Subtracted from `dy` each step.
+    gravity: i64,
+    /// How much `dx` and `dz` each move toward zero each step, without
+    /// crossing it.
+    drag: i64,
+    /// The fastest the probe can fall, if limited.
+    terminal_velocity: Option<i64>,
+    /// Added to `dx` and `dz` each step, after drag.
+    wind: (i64, i64),
+}
+
+impl Default for Physics {
+    /// The puzzle's physics.
+    fn default() -> Self {
+        Self {
+            gravity: 1,
+            drag: 1,
+            terminal_velocity: None,
+            wind: (0, 0),
+        }
+    }
+}
+
+impl Physics {
+    fn is_standard(&self) -> bool {
+        *self == Self::default()
+    }
 
-                if target_x.contains(&x) && target_y.contains(&y) {
-                    if max_y > overall_max_y {
-                        overall_max_y = max_y;
+    fn step(&self, position: &mut Vector, velocity: &mut Vector) {
+        position.0 += velocity.0;
+        position.1 += velocity.1;
+        position.2 += velocity.2;
+
+        let drag = |v: i64| v - v.signum() * v.abs().min(self.drag);
+        velocity.0 = drag(velocity.0) + self.wind.0;
+        velocity.1 = self.fall(velocity.1);
+        velocity.2 = drag(velocity.2) + self.wind.1;
+    }
+
+    fn fall(&self, dy: i64) -> i64 {
+        let dy = dy - self.gravity;
+        self.terminal_velocity.map_or(dy, |v| dy.max(-v))
+    }
+
+    /// Whether a probe at `position` moving at `velocity` can never get into
+    /// `target`. Only detects the probe falling below it, and moving away from
+    /// it in a direction without wind.
+    fn out_of_reach(&self, position: Vector, velocity: Vector, target: &Target) -> bool {
+        let moving_away = |p: i64, v: i64, wind: i64, range: &RangeInclusive<i64>| {
+            wind == 0
+                && self.drag >= 0
+                && ((p < *range.start() && v <= 0) || (p > *range.end() && v >= 0))
+        };
+
+        (self.gravity >= 0 && position.1 < *target.y.start() && velocity.1 < 0)
+            || moving_away(position.0, velocity.0, self.wind.0, &target.x)
+            || target
+                .z
+                .as_ref()
+                .is_some_and(|z| moving_away(position.2, velocity.2, self.wind.1, z))
+    }
+
+    /// The last step on which a probe launched sideways no faster than
+    /// `range` is far is within it, or `None` if some launch stops there.
+    /// Assumes no wind and no negative drag.
+    fn last_step_within(&self, range: &RangeInclusive<i64>) -> Option<i64> {
+        let mut last = 0;
+        let reach = reach(range);
+        for launch in -reach..=reach {
+            let (mut position, mut velocity, mut step) = (0, launch, 0);
+            loop {
+                if range.contains(&position) {
+                    if velocity == 0 {
+                        return None;
                     }
+                    last = last.max(step);
+                }
+                let beyond = (velocity > 0 && position > *range.end())
+                    || (velocity < 0 && position < *range.start());
+                if velocity == 0 || beyond {
                     break;
                 }
+                position += velocity;
+                velocity -= velocity.signum() * velocity.abs().min(self.drag);
+                step += 1;
+            }
+        }
+        Some(last)
+    }
 
-                if dx > 0 {
-                    dx -= 1;
-                }
-                dy -= 1;
+    /// The highest a probe launched at `dy` gets.
+    fn apex(&self, mut dy: i64) -> i64 {
+        if self.is_standard() {
+            return sum_to(dy.max(0));
+        }
+
+        let (mut y, mut highest) = (0, 0);
+        for _ in 0..MAX_STEPS {
+            if dy <= 0 {
+                break;
             }
+            y += dy;
+            highest = highest.max(y);
+            dy = self.fall(dy);
         }
+        highest
     }
+}
+
+/// How many steps a probe is simulated for at most, as it may never settle
+/// under unusual physics.
+const MAX_STEPS: usize = 10_000;
+
+/// Parses a target as in the puzzle input, optionally followed by
+/// `, z=<min>..<max>` for a box target.
+fn parse(s: &str) -> Option<Target> {
+    let s = s.strip_prefix("target area: ")?;
+    let mut parts = s.split(", ");
+    let range = |part: &str, axis: &str| {
+        let (min, max) = part.strip_prefix(axis)?.split_once("..")?;
+        Some(min.parse().ok()?..=max.parse().ok()?)
+    };
 
-    overall_max_y
+    let x = range(parts.next()?, "x=")?;
+    let y = range(parts.next()?, "y=")?;
+    let z = match parts.next() {
+        Some(part) => Some(range(part, "z=")?),
+        None => None,
+    };
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(Target { x, y, z })
+}
+
+fn problem1(physics: &Physics, target: &Target) -> i64 {
+    launches(physics, target)
+        .unwrap_or_else(|e| panic!("{}", e))
+        .into_iter()
+        .map(|(_, dy, _)| physics.apex(dy))
+        .max()
+        .expect("No velocity hits the target")
+}
+
+fn problem2(physics: &Physics, target: &Target) -> usize {
+    launches(physics, target)
+        .unwrap_or_else(|e| panic!("{}", e))
+        .len()
 }
 
-fn problem2(target_x: RangeInclusive<i32>, target_y: RangeInclusive<i32>) -> usize {
-    let mut min_dx = 1;
-    let max_dx = *target_x.end();
-    while !target_x.contains(&sum_to(min_dx)) {
-        min_dx += 1;
+/// Why the launches hitting a target can't be listed.
+#[derive(Debug, PartialEq, Eq)]
+enum LaunchError {
+    /// Infinitely many launches hit the target.
+    Infinite,
+    /// Launches outside those searched might hit the target.
+    Unsearchable,
+}
+
+impl fmt::Display for LaunchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Infinite => write!(f, "infinitely many velocities hit the target"),
+            Self::Unsearchable => write!(f, "unable to search every velocity under the physics"),
+        }
     }
+}
 
-    let mut successes = Vec::new();
-    for original_dx in min_dx..=max_dx {
-        for original_dy in -1000..1000 {
-            let mut dx = original_dx;
-            let mut dy = original_dy;
-            let mut x = 0;
-            let mut y = 0;
-            let mut max_y = 0;
-            while &x <= target_x.end() && &y >= target_y.start() {
-                x += dx;
-                y += dy;
+/// Every initial velocity that puts the probe inside the target after some
+/// step under `physics`, sorted. For 2D targets, `dz` is always zero.
+fn launches(physics: &Physics, target: &Target) -> Result<Vec<Vector>, LaunchError> {
+    if physics.is_standard() {
+        velocities(target).ok_or(LaunchError::Infinite)
+    } else {
+        let max_dy = search_limit(physics, target)?;
+        Ok(search(physics, target, max_dy))
+    }
+}
 
-                if y > max_y {
-                    max_y = y;
-                }
+/// The fastest upward launch `search` needs to try to find every launch
+/// under `physics`.
+///
+/// Without wind or negative drag, a probe never speeds up sideways or turns
+/// back, so one launched further than the target is far on its first step
+/// never returns. Likewise for a probe launched downwards, as long as
+/// gravity pulls it down. One launched upwards comes back down through
+/// exactly its launch height only while gravity is 1 or 2, and unlimited by
+/// a terminal velocity, after which it falls further than it was launched;
+/// other gravities let arbitrarily fast launches land close to the origin.
+/// So launches upwards faster than the target is far can only hit it on
+/// their way back through the launch height, which takes longer the faster
+/// they are. If the probe can stop sideways within the target there, that
+/// makes infinitely many launches.
+fn search_limit(physics: &Physics, target: &Target) -> Result<i64, LaunchError> {
+    if physics.wind != (0, 0)
+        || physics.drag < 0
+        || !matches!(physics.gravity, 1 | 2)
+        || physics.terminal_velocity.is_some()
+    {
+        return Err(LaunchError::Unsearchable);
+    }
 
-                if target_x.contains(&x) && target_y.contains(&y) {
-                    successes.push((original_dx, original_dy));
-                    break;
-                }
+    let mut max_dy = reach(&target.y);
+    if target.y.contains(&0) {
+        // Within the target sideways on no step after this, if limited.
+        let last_step = [Some(&target.x), target.z.as_ref()]
+            .into_iter()
+            .flatten()
+            .filter_map(|range| physics.last_step_within(range))
+            .min()
+            .ok_or(LaunchError::Infinite)?;
+        // Back through the launch height at step `2 * dy / gravity + 1`.
+        max_dy = max_dy.max(last_step * physics.gravity / 2);
+    }
+
+    // Every hit then happens within `2 * max_dy + 2` steps.
+    if 2 * max_dy + 2 > MAX_STEPS as i64 {
+        return Err(LaunchError::Unsearchable);
+    }
+    Ok(max_dy)
+}
 
-                if dx > 0 {
-                    dx -= 1;
+/// The furthest the target is from the origin along one axis.
+fn reach(range: &RangeInclusive<i64>) -> i64 {
+    range.start().abs().max(range.end().abs())
+}
+
+/// Finds launches by simulating each one no faster sideways or downwards
+/// than the target is far, and no faster upwards than `max_dy`, which covers
+/// all of them when `search_limit` says so.
+fn search(physics: &Physics, target: &Target, max_dy: i64) -> Vec<Vector> {
+    let (reach_x, reach_y) = (reach(&target.x), reach(&target.y));
+    let reach_z = target.z.as_ref().map_or(0, reach);
+
+    let mut launches = Vec::new();
+    for dx in -reach_x..=reach_x {
+        for dy in -reach_y..=max_dy {
+            for dz in -reach_z..=reach_z {
+                if trajectory(physics, (dx, dy, dz), target).hit.is_some() {
+                    launches.push((dx, dy, dz));
                 }
-                dy -= 1;
             }
         }
     }
+    launches
+}
+
+/// The launches under the puzzle's physics, computed analytically.
+///
+/// For each `dx`, the steps at which the probe is within the target's x range
+/// form a window, and likewise for `dz`. For each step `n` in both windows,
+/// the `dy` putting the probe within the y range at that step form a range,
+/// since the height after `n` steps is `n * dy - sum_to(n - 1)`.
+fn velocities(target: &Target) -> Option<Vec<Vector>> {
+    let windows = |range: &RangeInclusive<i64>| {
+        // A probe moving away from the target never comes back.
+        ((*range.start()).min(0)..=(*range.end()).max(0))
+            .filter_map(|d| Some((d, step_window(d, range)?)))
+            .collect::<Vec<_>>()
+    };
+    let x_windows = windows(&target.x);
+    let z_windows = match &target.z {
+        Some(z) => windows(z),
+        None => vec![(0, (1, None))],
+    };
+
+    let mut windows = Vec::new();
+    for (dx, (x_first, x_last)) in &x_windows {
+        for (dz, (z_first, z_last)) in &z_windows {
+            let first = *x_first.max(z_first);
+            let last = match (x_last, z_last) {
+                (Some(x), Some(z)) => Some(*x.min(z)),
+                (last, None) | (None, last) => *last,
+            };
+            if last.is_none_or(|last| first <= last) {
+                windows.push((*dx, *dz, first, last));
+            }
+        }
+    }
+    // Probes stopping within the x (and z) range hit it on every later step.
+    let last_step = if windows.iter().any(|(_, _, _, last)| last.is_none()) {
+        Some(last_step(&target.y)?)
+    } else {
+        None
+    };
+
+    let mut velocities = Vec::new();
+    for (dx, dz, first, last) in windows {
+        let mut ranges = (first..=last.or(last_step).unwrap())
+            .map(|n| dy_range(n, &target.y))
+            .filter(|r| !r.is_empty())
+            .collect::<Vec<_>>();
+        ranges.sort_by_key(|r| *r.start());
 
-    successes.len()
+        // Different steps may share `dy` values, so merge the ranges first.
+        let mut next_dy = i64::MIN;
+        for range in ranges {
+            for dy in next_dy.max(*range.start())..=*range.end() {
+                velocities.push((dx, dy, dz));
+            }
+            next_dy = next_dy.max(range.end() + 1);
+        }
+    }
+
+    velocities.sort_unstable();
+    Some(velocities)
 }
 
-fn sum_to(n: i32) -> i32 {
+/// The first and last steps after which a probe launched with `dx` is within
+/// `target_x`, the last being `None` if it stops there.
+fn step_window(dx: i64, target_x: &RangeInclusive<i64>) -> Option<(i64, Option<i64>)> {
+    // Mirror leftward launches, so the probe moves right.
+    let (dx, min_x, max_x) = if dx < 0 {
+        (-dx, -target_x.end(), -target_x.start())
+    } else {
+        (dx, *target_x.start(), *target_x.end())
+    };
+    // Never decreasing, and constant from step `dx` on.
+    let x = |n: i64| {
+        let n = n.min(dx);
+        n * dx - sum_to(n - 1)
+    };
+
+    let first = first_step(dx, |n| x(n) >= min_x)?.max(1);
+    let last = first_step(dx, |n| x(n) > max_x).map(|n| n - 1);
+    if last.is_some_and(|last| last < first) {
+        return None;
+    }
+    Some((first, last))
+}
+
+/// The smallest step in `0..=max` for which `pred` holds, assuming it keeps
+/// holding once it does.
+fn first_step(max: i64, pred: impl Fn(i64) -> bool) -> Option<i64> {
+    if !pred(max) {
+        return None;
+    }
+
+    let (mut low, mut high) = (0, max);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if pred(mid) {
+            high = mid;
+        } else {
+            low = mid + 1;
+        }
+    }
+    Some(low)
+}
+
+/// The `dy` putting the probe within `target_y` after step `n`.
+fn dy_range(n: i64, target_y: &RangeInclusive<i64>) -> RangeInclusive<i64> {
+    let drop = sum_to(n - 1);
+    let low = -(-(target_y.start() + drop)).div_euclid(n);
+    let high = (target_y.end() + drop).div_euclid(n);
+    low..=high
+}
+
+/// The last step after which any probe can still be within `target_y`, or
+/// `None` if there is no such step.
+fn last_step(target_y: &RangeInclusive<i64>) -> Option<i64> {
+    let (min_y, max_y) = (*target_y.start(), *target_y.end());
+    // A probe launched upwards comes back down through the heights it went up
+    // through, passing `0` and then `-dy - 1`. So only launches at up to
+    // `max_y` can hit a target above the origin, and only launches at up to
+    // `-min_y - 1` one below it.
+    let max_dy = if min_y > 0 {
+        max_y
+    } else if max_y < 0 {
+        -min_y - 1
+    } else {
+        return None;
+    };
+
+    // The larger root of `n * max_dy - sum_to(n - 1) = min_y`, corrected for
+    // rounding.
+    let y = |n: i64| n * max_dy - sum_to(n - 1);
+    let b = (2 * max_dy + 1) as f64;
+    let mut n = ((b + (b * b - 8.0 * min_y as f64).sqrt()) / 2.0) as i64;
+    while y(n + 1) >= min_y {
+        n += 1;
+    }
+    while n > 0 && y(n) < min_y {
+        n -= 1;
+    }
+    Some(n)
+}
+
+/// The path of a probe, up to when it is first within the target or can no
+/// longer reach it.
+#[derive(Debug, PartialEq, Eq)]
+struct Trajectory {
+    /// The position after each step, starting from the launch at step 0.
+    positions: Vec<Vector>,
+    /// The step after which the probe is first within the target.
+    hit: Option<usize>,
+}
+
+fn trajectory(physics: &Physics, mut velocity: Vector, target: &Target) -> Trajectory {
+    let mut position = (0, 0, 0);
+    let mut positions = vec![position];
+    for _ in 0..MAX_STEPS {
+        physics.step(&mut position, &mut velocity);
+        positions.push(position);
+
+        if target.contains(position) {
+            return Trajectory {
+                hit: Some(positions.len() - 1),
+                positions,
+            };
+        }
+        if physics.out_of_reach(position, velocity, target) {
+            break;
+        }
+    }
+
+    Trajectory {
+        positions,
+        hit: None,
+    }
+}
+
+/// Draws the trajectory like the puzzle does, with `S` for the launch
+/// position, `#` for the probe and `T` for the target, seen along the z axis.
+fn render(trajectory: &Trajectory, target: &Target) -> Result<String, MapTooLarge> {
+    let cells = cells(trajectory, target)?;
+    let mut output = String::new();
+    for row in cells {
+        output.extend(row.into_iter().map(|cell| match cell {
+            Cell::Empty => '.',
+            Cell::Start => 'S',
+            Cell::Probe => '#',
+            Cell::Target => 'T',
+        }));
+        output.push('\n');
+    }
+    Ok(output)
+}
+
+/// Draws the trajectory as a binary PPM image, one pixel per position.
+fn render_ppm(trajectory: &Trajectory, target: &Target) -> Result<Vec<u8>, MapTooLarge> {
+    let cells = cells(trajectory, target)?;
+    let mut image = format!("P6\n{} {}\n255\n", cells[0].len(), cells.len()).into_bytes();
+    for cell in cells.into_iter().flatten() {
+        image.extend(match cell {
+            Cell::Empty => [0, 0, 0],
+            Cell::Start => [255, 64, 64],
+            Cell::Probe => [255, 255, 255],
+            Cell::Target => [64, 96, 255],
+        });
+    }
+    Ok(image)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Cell {
+    Empty,
+    Start,
+    Probe,
+    Target,
+}
+
+/// The cells covering the trajectory and target, top row first, if there
+/// are not too many.
+fn cells(trajectory: &Trajectory, target: &Target) -> Result<Vec<Vec<Cell>>, MapTooLarge> {
+    let (target_x, target_y) = (&target.x, &target.y);
+    let xs = trajectory.positions.iter().map(|(x, _, _)| *x);
+    let ys = trajectory.positions.iter().map(|(_, y, _)| *y);
+    let min_x = xs.clone().chain([*target_x.start()]).min().unwrap();
+    let max_x = xs.chain([*target_x.end()]).max().unwrap();
+    let min_y = ys.clone().chain([*target_y.start()]).min().unwrap();
+    let max_y = ys.chain([*target_y.end()]).max().unwrap();
+    check_map_size((min_x, min_y), (max_x, max_y))?;
+
+    let mut cells =
+        vec![vec![Cell::Empty; (max_x - min_x + 1) as usize]; (max_y - min_y + 1) as usize];
+    let mut set = |x: i64, y: i64, cell| cells[(max_y - y) as usize][(x - min_x) as usize] = cell;
+    for y in target_y.clone() {
+        for x in target_x.clone() {
+            set(x, y, Cell::Target);
+        }
+    }
+    for (x, y, _) in &trajectory.positions[1..] {
+        set(*x, *y, Cell::Probe);
+    }
+    set(0, 0, Cell::Start);
+    Ok(cells)
+}
+
+fn sum_to(n: i64) -> i64 {
     n * (n + 1) / 2
 }
 
@@ -127,13 +570,244 @@ mod tests {
 
     #[test]
     fn example1() {
-        let (x, y) = parse(SAMPLE);
-        assert_eq!(problem1(x, y), 45);
+        assert_eq!(problem1(&Physics::default(), &parse(SAMPLE).unwrap()), 45);
     }
 
     #[test]
     fn example2() {
-        let (x, y) = parse(SAMPLE);
-        assert_eq!(problem2(x, y), 112);
+        assert_eq!(problem2(&Physics::default(), &parse(SAMPLE).unwrap()), 112);
+    }
+
+    /// Velocities hitting the target found by simulating every launch with
+    /// components within `-max..=max`.
+    fn brute_force(target: &Target, max: i64) -> Vec<Vector> {
+        let dzs = if target.z.is_some() {
+            -max..=max
+        } else {
+            0..=0
+        };
+        let mut velocities = Vec::new();
+        for original_dx in -max..=max {
+            for original_dy in -max..=max {
+                for original_dz in dzs.clone() {
+                    let (mut x, mut y, mut z) = (0, 0, 0);
+                    let (mut dx, mut dy, mut dz) = (original_dx, original_dy, original_dz);
+                    for _ in 0..200 {
+                        x += dx;
+                        y += dy;
+                        z += dz;
+                        dx -= dx.signum();
+                        dy -= 1;
+                        dz -= dz.signum();
+                        if target.contains((x, y, z)) {
+                            velocities.push((original_dx, original_dy, original_dz));
+                            break;
+                        }
+                        if y < *target.y.start() && dy < 0 {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        velocities
+    }
+
+    #[test]
+    fn any_quadrant() {
+        for (x, y) in [
+            (20..=30, -10..=-5),
+            (-30..=-20, -10..=-5),
+            (20..=30, 5..=10),
+            (-30..=-20, 5..=10),
+            (-5..=5, -10..=-5),
+            (22..=27, -5..=5),
+            (-27..=-22, 0..=0),
+            (10..=10, 3..=3),
+        ] {
+            let target = Target { x, y, z: None };
+            assert_eq!(
+                velocities(&target),
+                Some(brute_force(&target, 60)),
+                "{:?}",
+                target
+            );
+        }
+    }
+
+    #[test]
+    fn infinite() {
+        let target = Target {
+            x: 20..=30,
+            y: -5..=5,
+            z: None,
+        };
+        assert_eq!(velocities(&target), None);
+        let target = Target {
+            x: -1..=1,
+            y: 0..=0,
+            z: None,
+        };
+        assert_eq!(velocities(&target), None);
+    }
+
+    #[test]
+    fn deep_target() {
+        let target = Target {
+            x: 2000..=2100,
+            y: -5000..=-4990,
+            z: None,
+        };
+        assert_eq!(problem1(&Physics::default(), &target), 4999 * 5000 / 2);
+    }
+
+    #[test]
+    fn box_target() {
+        let target = parse("target area: x=20..30, y=-10..-5, z=-8..-4").unwrap();
+        assert_eq!(target.z, Some(-8..=-4));
+        let expected = brute_force(&target, 30);
+        assert_eq!(velocities(&target), Some(expected.clone()));
+        assert_eq!(search(&Physics::default(), &target, 10), expected);
+
+        let target = parse("target area: x=-3..3, y=-10..-5, z=10..10").unwrap();
+        assert_eq!(velocities(&target), Some(brute_force(&target, 30)));
+    }
+
+    #[test]
+    fn custom_physics() {
+        let physics = Physics {
+            gravity: 2,
+            drag: 0,
+            terminal_velocity: Some(3),
+            wind: (1, 0),
+        };
+        let target = parse(SAMPLE).unwrap();
+        let trajectory = trajectory(&physics, (1, 4, 0), &target);
+        assert_eq!(
+            trajectory.positions,
+            vec![
+                (0, 0, 0),
+                (1, 4, 0),
+                (3, 6, 0),
+                (6, 6, 0),
+                (10, 4, 0),
+                (15, 1, 0),
+                (21, -2, 0),
+                (28, -5, 0)
+            ]
+        );
+        assert_eq!(trajectory.hit, Some(7));
+        assert_eq!(physics.apex(4), 6);
+
+        // The wind lets launches away from the target come back to it.
+        assert_eq!(launches(&physics, &target), Err(LaunchError::Unsearchable));
+        let physics = Physics {
+            gravity: 3,
+            drag: 0,
+            terminal_velocity: None,
+            wind: (0, 0),
+        };
+        // Launched upwards at 10, the probe comes down to -4.
+        let target = parse("target area: x=0..0, y=-4..-4").unwrap();
+        assert!(super::trajectory(&physics, (0, 10, 0), &target)
+            .hit
+            .is_some());
+        assert_eq!(launches(&physics, &target), Err(LaunchError::Unsearchable));
+
+        let physics = Physics {
+            gravity: 2,
+            drag: 2,
+            terminal_velocity: None,
+            wind: (0, 0),
+        };
+        let target = parse(SAMPLE).unwrap();
+        let found = launches(&physics, &target).unwrap();
+        let mut expected = Vec::new();
+        for dx in -90..=90 {
+            for dy in -30..=30 {
+                if super::trajectory(&physics, (dx, dy, 0), &target)
+                    .hit
+                    .is_some()
+                {
+                    expected.push((dx, dy, 0));
+                }
+            }
+        }
+        assert_eq!(found, expected);
+
+        assert_eq!(
+            search(&Physics::default(), &target, 10),
+            velocities(&target).unwrap()
+        );
+
+        // Probes launched ever faster upwards come back down through the
+        // target once they have stopped sideways within it.
+        let physics = Physics {
+            gravity: 2,
+            drag: 1,
+            terminal_velocity: None,
+            wind: (0, 0),
+        };
+        let target = parse("target area: x=20..30, y=-5..5").unwrap();
+        assert_eq!(
+            super::trajectory(&physics, (6, 100, 0), &target).hit,
+            Some(101)
+        );
+        assert_eq!(launches(&physics, &target), Err(LaunchError::Infinite));
+        // Without drag they never stop, so only launches slow enough to be
+        // back before leaving it hit.
+        let physics = Physics { drag: 0, ..physics };
+        let found = launches(&physics, &target).unwrap();
+        assert!(found.contains(&(1, 20, 0)));
+        let mut expected = Vec::new();
+        for dx in -30..=30 {
+            for dy in -100..=100 {
+                if super::trajectory(&physics, (dx, dy, 0), &target)
+                    .hit
+                    .is_some()
+                {
+                    expected.push((dx, dy, 0));
+                }
+            }
+        }
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn trajectories() {
+        let target = parse(SAMPLE).unwrap();
+        let physics = Physics::default();
+        let hit = trajectory(&physics, (7, 2, 0), &target);
+        assert_eq!(hit.hit, Some(7));
+        assert_eq!(hit.positions[7], (28, -7, 0));
+        assert_eq!(
+            render(&hit, &target).unwrap(),
+            ".............#....#............\n\
+             .......#..............#........\n\
+             ...............................\n\
+             S........................#.....\n\
+             ...............................\n\
+             ...............................\n\
+             ...........................#...\n\
+             ...............................\n\
+             ....................TTTTTTTTTTT\n\
+             ....................TTTTTTTTTTT\n\
+             ....................TTTTTTTT#TT\n\
+             ....................TTTTTTTTTTT\n\
+             ....................TTTTTTTTTTT\n\
+             ....................TTTTTTTTTTT\n"
+        );
+
+        let miss = trajectory(&physics, (17, -4, 0), &target);
+        assert_eq!(miss.hit, None);
+        assert_eq!(miss.positions, vec![(0, 0, 0), (17, -4, 0), (33, -9, 0)]);
+
+        for velocity in velocities(&target).unwrap() {
+            assert!(trajectory(&physics, velocity, &target).hit.is_some());
+        }
+
+        let far = trajectory(&physics, (5000, 5000, 0), &target);
+        assert!(render(&far, &target).is_err());
+        assert!(render_ppm(&far, &target).is_err());
     }
 }
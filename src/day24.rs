@@ -1,12 +1,807 @@
-use rayon::prelude::*;
+use crate::rng::Rng;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::ops::{Range, RangeInclusive};
+use std::str::FromStr;
 
 pub fn run() {
+    let content = fs::read_to_string("input/day24").expect("Unable to read input");
+    let program =
+        parse_program(&content).unwrap_or_else(|e| panic!("Unable to parse input: {}", e));
+
     println!("Day 24");
-    println!("  Problem 1: {}", problem1());
+    println!("  Problem 1: {}", problem1(&program));
+    println!("  Problem 2: {}", problem2(&program));
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Register {
+    W,
+    X,
+    Y,
+    Z,
+}
+
+impl Register {
+    fn index(self) -> usize {
+        match self {
+            Self::W => 0,
+            Self::X => 1,
+            Self::Y => 2,
+            Self::Z => 3,
+        }
+    }
+}
+
+impl FromStr for Register {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "w" => Ok(Self::W),
+            "x" => Ok(Self::X),
+            "y" => Ok(Self::Y),
+            "z" => Ok(Self::Z),
+            _ => Err(format!("unknown register `{}`", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Operand {
+    Register(Register),
+    Literal(i64),
+}
+
+impl FromStr for Operand {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        if let Ok(register) = s.parse() {
+            return Ok(Self::Register(register));
+        }
+        s.parse()
+            .map(Self::Literal)
+            .map_err(|_| format!("`{}` is neither a register nor an integer", s))
+    }
+}
+
+/// An ALU instruction. `set` and `neq` never appear in puzzle input, but are
+/// produced by `optimize`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Instruction {
+    Inp(Register),
+    Add(Register, Operand),
+    Mul(Register, Operand),
+    Div(Register, Operand),
+    Mod(Register, Operand),
+    Eql(Register, Operand),
+    Neq(Register, Operand),
+    Set(Register, Operand),
+}
+
+impl Instruction {
+    fn mnemonic(self) -> &'static str {
+        match self {
+            Self::Inp(_) => "inp",
+            Self::Add(..) => "add",
+            Self::Mul(..) => "mul",
+            Self::Div(..) => "div",
+            Self::Mod(..) => "mod",
+            Self::Eql(..) => "eql",
+            Self::Neq(..) => "neq",
+            Self::Set(..) => "set",
+        }
+    }
+
+    fn target(self) -> Register {
+        match self {
+            Self::Inp(a)
+            | Self::Add(a, _)
+            | Self::Mul(a, _)
+            | Self::Div(a, _)
+            | Self::Mod(a, _)
+            | Self::Eql(a, _)
+            | Self::Neq(a, _)
+            | Self::Set(a, _) => a,
+        }
+    }
+
+    fn operand(self) -> Option<Operand> {
+        match self {
+            Self::Inp(_) => None,
+            Self::Add(_, b)
+            | Self::Mul(_, b)
+            | Self::Div(_, b)
+            | Self::Mod(_, b)
+            | Self::Eql(_, b)
+            | Self::Neq(_, b)
+            | Self::Set(_, b) => Some(b),
+        }
+    }
+
+    fn with_operand(self, b: Operand) -> Self {
+        match self {
+            Self::Inp(a) => Self::Inp(a),
+            Self::Add(a, _) => Self::Add(a, b),
+            Self::Mul(a, _) => Self::Mul(a, b),
+            Self::Div(a, _) => Self::Div(a, b),
+            Self::Mod(a, _) => Self::Mod(a, b),
+            Self::Eql(a, _) => Self::Eql(a, b),
+            Self::Neq(a, _) => Self::Neq(a, b),
+            Self::Set(a, _) => Self::Set(a, b),
+        }
+    }
+
+    /// Computes the new value of the target register, given its current
+    /// value `lhs` and the value of the operand `rhs`. Not valid for `inp`.
+    fn apply(self, lhs: i64, rhs: i64, pc: usize) -> Result<i64, AluError> {
+        match self {
+            Self::Inp(_) => unreachable!("inp has no operand to apply"),
+            Self::Add(..) => lhs.checked_add(rhs).ok_or(AluError::Overflow(pc)),
+            Self::Mul(..) => lhs.checked_mul(rhs).ok_or(AluError::Overflow(pc)),
+            Self::Div(..) if rhs == 0 => Err(AluError::DivisionByZero(pc)),
+            Self::Div(..) => lhs.checked_div(rhs).ok_or(AluError::Overflow(pc)),
+            Self::Mod(..) if rhs == 0 => Err(AluError::ModuloByZero(pc)),
+            Self::Mod(..) if lhs < 0 || rhs < 0 => Err(AluError::NegativeModulo(pc)),
+            Self::Mod(..) => Ok(lhs % rhs),
+            Self::Eql(..) => Ok((lhs == rhs).into()),
+            Self::Neq(..) => Ok((lhs != rhs).into()),
+            Self::Set(..) => Ok(rhs),
+        }
+    }
+}
+
+impl FromStr for Instruction {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut parts = s.split_whitespace();
+        let op = parts.next().ok_or("missing instruction")?;
+        let a = parts
+            .next()
+            .ok_or_else(|| format!("`{}` is missing its first operand", op))?
+            .parse()?;
+
+        let instruction = if op == "inp" {
+            Self::Inp(a)
+        } else {
+            let b = parts
+                .next()
+                .ok_or_else(|| format!("`{}` is missing its second operand", op))?
+                .parse()?;
+            match op {
+                "add" => Self::Add(a, b),
+                "mul" => Self::Mul(a, b),
+                "div" => Self::Div(a, b),
+                "mod" => Self::Mod(a, b),
+                "eql" => Self::Eql(a, b),
+                "neq" => Self::Neq(a, b),
+                "set" => Self::Set(a, b),
+                _ => return Err(format!("unknown instruction `{}`", op)),
+            }
+        };
+
+        match parts.next() {
+            Some(extra) => Err(format!("unexpected trailing `{}`", extra)),
+            None => Ok(instruction),
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Self::W => "w",
+            Self::X => "x",
+            Self::Y => "y",
+            Self::Z => "z",
+        };
+        f.write_str(name)
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Register(r) => r.fmt(f),
+            Self::Literal(v) => v.fmt(f),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.mnemonic(), self.target())?;
+        match self.operand() {
+            Some(b) => write!(f, " {}", b),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct ParseError {
+    line: usize,
+    message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+fn parse_program(raw: &str) -> Result<Vec<Instruction>, ParseError> {
+    raw.lines()
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty())
+        .map(|(i, l)| {
+            l.parse().map_err(|message| ParseError {
+                line: i + 1,
+                message,
+            })
+        })
+        .collect()
 }
 
+/// Errors raised while executing a program. Each carries the index of the
+/// offending instruction.
+#[derive(Debug, PartialEq, Eq)]
+enum AluError {
+    InputExhausted(usize),
+    DivisionByZero(usize),
+    ModuloByZero(usize),
+    NegativeModulo(usize),
+    Overflow(usize),
+}
+
+fn execute(
+    program: &[Instruction],
+    input: impl Iterator<Item = i64>,
+) -> Result<(i64, i64, i64, i64), AluError> {
+    let [w, x, y, z] = execute_from([0; 4], program, input)?;
+    Ok((w, x, y, z))
+}
+
+fn execute_from(
+    mut registers: [i64; 4],
+    program: &[Instruction],
+    mut input: impl Iterator<Item = i64>,
+) -> Result<[i64; 4], AluError> {
+    for (pc, instruction) in program.iter().enumerate() {
+        let a = instruction.target().index();
+        registers[a] = match instruction.operand() {
+            None => input.next().ok_or(AluError::InputExhausted(pc))?,
+            Some(Operand::Register(b)) => {
+                instruction.apply(registers[a], registers[b.index()], pc)?
+            }
+            Some(Operand::Literal(b)) => instruction.apply(registers[a], b, pc)?,
+        };
+    }
+
+    Ok(registers)
+}
+
+/// Rewrites `program` into a shorter program that leaves the registers in
+/// `live_out` unchanged. Only executions that do not fault are preserved.
+fn optimize(program: &[Instruction], live_out: &[Register]) -> Vec<Instruction> {
+    let mut program = program.to_vec();
+    loop {
+        let optimized = eliminate_dead_stores(&propagate_constants(&fold_neq(&program)), live_out);
+        if optimized == program {
+            return optimized;
+        }
+        program = optimized;
+    }
+}
+
+/// Replaces `eql a b; eql a 0` with `neq a b`.
+fn fold_neq(program: &[Instruction]) -> Vec<Instruction> {
+    let mut folded = Vec::new();
+    let mut i = 0;
+    while i < program.len() {
+        match (program[i], program.get(i + 1)) {
+            (Instruction::Eql(a, b), Some(Instruction::Eql(c, Operand::Literal(0)))) if a == *c => {
+                folded.push(Instruction::Neq(a, b));
+                i += 2;
+            }
+            (instruction, _) => {
+                folded.push(instruction);
+                i += 1;
+            }
+        }
+    }
+    folded
+}
+
+/// Tracks registers with a statically known value, folding instructions that
+/// only involve known values into `set` and dropping those that are no-ops.
+fn propagate_constants(program: &[Instruction]) -> Vec<Instruction> {
+    let mut known = [Some(0i64); 4];
+    let mut propagated = Vec::new();
+
+    for (pc, instruction) in program.iter().enumerate() {
+        let a = instruction.target();
+        let mut instruction = *instruction;
+        if let Some(Operand::Register(b)) = instruction.operand() {
+            if let Some(v) = known[b.index()] {
+                instruction = instruction.with_operand(Operand::Literal(v));
+            }
+        }
+
+        let lhs = known[a.index()];
+        let rhs = match instruction.operand() {
+            Some(Operand::Literal(v)) => Some(v),
+            _ => None,
+        };
+        let folded = match (instruction, lhs, rhs) {
+            (Instruction::Inp(_), _, _) => None,
+            (Instruction::Set(_, b), _, _) if b == Operand::Register(a) => continue,
+            (Instruction::Set(..), _, rhs) => rhs,
+            (_, Some(lhs), Some(rhs)) => instruction.apply(lhs, rhs, pc).ok(),
+            (Instruction::Mul(..), _, Some(0)) | (Instruction::Mul(..), Some(0), _) => Some(0),
+            (Instruction::Add(..), _, Some(0))
+            | (Instruction::Mul(..), _, Some(1))
+            | (Instruction::Div(..), _, Some(1)) => continue,
+            (Instruction::Add(_, b), Some(0), _) => {
+                propagated.push(Instruction::Set(a, b));
+                known[a.index()] = None;
+                continue;
+            }
+            _ => None,
+        };
+
+        match folded {
+            Some(v) if lhs == Some(v) => (),
+            Some(v) => propagated.push(Instruction::Set(a, Operand::Literal(v))),
+            None => propagated.push(instruction),
+        }
+        known[a.index()] = folded;
+    }
+
+    propagated
+}
+
+/// Drops instructions whose result is overwritten or never read before the
+/// end of the program. `inp` is always kept since it consumes input.
+fn eliminate_dead_stores(program: &[Instruction], live_out: &[Register]) -> Vec<Instruction> {
+    let mut live = [false; 4];
+    for r in live_out {
+        live[r.index()] = true;
+    }
+
+    let mut kept = Vec::new();
+    for instruction in program.iter().rev() {
+        if !live[instruction.target().index()] && !matches!(instruction, Instruction::Inp(_)) {
+            continue;
+        }
+
+        update_liveness(*instruction, &mut live);
+        kept.push(*instruction);
+    }
+
+    kept.reverse();
+    kept
+}
+
+/// Turns the set of registers live after `instruction` into the set live
+/// before it.
+fn update_liveness(instruction: Instruction, live: &mut [bool; 4]) {
+    live[instruction.target().index()] =
+        !matches!(instruction, Instruction::Inp(_) | Instruction::Set(..));
+    if let Some(Operand::Register(b)) = instruction.operand() {
+        live[b.index()] = true;
+    }
+}
+
+/// Renders `program` as a Rust function with the signature of the one
+/// `build_alu!` generates, one statement per instruction and a comment
+/// numbering each input block.
+fn emit_rust(name: &str, program: &[Instruction]) -> String {
+    let mut out = format!(
+        "#[allow(unused_assignments, unused_mut)]\n\
+         fn {}(mut input: impl Iterator<Item = i64>) -> (i64, i64, i64, i64) {{\n\
+         \x20   let mut w = 0i64;\n\
+         \x20   let mut x = 0i64;\n\
+         \x20   let mut y = 0i64;\n\
+         \x20   let mut z = 0i64;\n",
+        name
+    );
+
+    let mut block = 0;
+    for instruction in program {
+        let a = instruction.target();
+        let line = match *instruction {
+            Instruction::Inp(_) => {
+                block += 1;
+                out += &format!("\n    // {}\n", block);
+                format!("{} = input.next().unwrap();", a)
+            }
+            Instruction::Add(_, b) => format!("{} += {};", a, b),
+            Instruction::Mul(_, b) => format!("{} *= {};", a, b),
+            Instruction::Div(_, b) => format!("{} /= {};", a, b),
+            Instruction::Mod(_, b) => format!("{} %= {};", a, b),
+            Instruction::Eql(_, b) => format!("{} = ({} == {}) as i64;", a, a, b),
+            Instruction::Neq(_, b) => format!("{} = ({} != {}) as i64;", a, a, b),
+            Instruction::Set(_, b) => format!("{} = {};", a, b),
+        };
+        out += &format!("    {}\n", line);
+    }
+
+    out += "\n    (w, x, y, z)\n}\n";
+    out
+}
+
+/// Runs both programs on `trials` pseudo-random inputs drawn from `digits`
+/// and compares the registers in `live_out`. Returns the first input on which
+/// they disagree. Inputs on which `original` faults are skipped.
+fn check_equivalence(
+    original: &[Instruction],
+    optimized: &[Instruction],
+    live_out: &[Register],
+    digits: RangeInclusive<i64>,
+    trials: usize,
+    seed: u64,
+) -> Result<(), Vec<i64>> {
+    let num_inputs = original
+        .iter()
+        .filter(|i| matches!(i, Instruction::Inp(_)))
+        .count();
+    let span = (digits.end() - digits.start() + 1) as u64;
+
+    let mut rng = Rng::new(seed);
+    let mut next_digit = || digits.start() + rng.below(span) as i64;
+
+    for _ in 0..trials {
+        let input: Vec<_> = (0..num_inputs).map(|_| next_digit()).collect();
+        let expected = match execute(original, input.iter().copied()) {
+            Ok(r) => r,
+            Err(_) => continue,
+        };
+        let actual = execute(optimized, input.iter().copied());
+
+        let same = actual.is_ok_and(|actual| {
+            let (expected, actual) = (<[i64; 4]>::from(expected), <[i64; 4]>::from(actual));
+            live_out
+                .iter()
+                .all(|r| expected[r.index()] == actual[r.index()])
+        });
+        if !same {
+            return Err(input);
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints an optimized Rust version of the ALU program at `path`, assuming
+/// only `z` is of interest.
+pub fn emit_command(path: &str) -> Result<(), String> {
+    let program = read_program(path)?;
+
+    let live_out = [Register::Z];
+    let optimized = optimize(&program, &live_out);
+    if let Err(input) = check_equivalence(&program, &optimized, &live_out, 1..=9, 10_000, 1) {
+        return Err(format!("Optimized program differs on input {:?}", input));
+    }
+
+    print!("{}", emit_rust("alu", &optimized));
+    Ok(())
+}
+
+/// A closed range of values a register may hold.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Interval {
+    min: i64,
+    max: i64,
+}
+
+impl Interval {
+    const FULL: Self = Self::new(i64::MIN, i64::MAX);
+
+    const fn new(min: i64, max: i64) -> Self {
+        Self { min, max }
+    }
+
+    const fn exact(v: i64) -> Self {
+        Self::new(v, v)
+    }
+
+    fn contains(self, v: i64) -> bool {
+        self.min <= v && v <= self.max
+    }
+
+    fn hull(values: impl IntoIterator<Item = i64>) -> Self {
+        values
+            .into_iter()
+            .fold(Self::new(i64::MAX, i64::MIN), |acc, v| {
+                Self::new(acc.min.min(v), acc.max.max(v))
+            })
+    }
+
+    /// Splits off the part of the interval that is at most `max`.
+    fn at_most(self, max: i64) -> Option<Self> {
+        (self.min <= max).then(|| Self::new(self.min, self.max.min(max)))
+    }
+
+    /// Splits off the part of the interval that is at least `min`.
+    fn at_least(self, min: i64) -> Option<Self> {
+        (self.max >= min).then(|| Self::new(self.min.max(min), self.max))
+    }
+}
+
+impl Instruction {
+    /// The interval counterpart of `apply`. Arithmetic saturates, since an
+    /// overflowing execution faults anyway. Returns `None` if every
+    /// execution faults.
+    fn apply_interval(self, lhs: Interval, rhs: Interval) -> Option<Interval> {
+        let corners = |f: fn(i64, i64) -> i64, lhs: Interval, rhs: Interval| {
+            Interval::hull([
+                f(lhs.min, rhs.min),
+                f(lhs.min, rhs.max),
+                f(lhs.max, rhs.min),
+                f(lhs.max, rhs.max),
+            ])
+        };
+        let compare = |equal: bool| {
+            if lhs.min == lhs.max && lhs == rhs {
+                Interval::exact(equal.into())
+            } else if lhs.max < rhs.min || rhs.max < lhs.min {
+                Interval::exact((!equal).into())
+            } else {
+                Interval::new(0, 1)
+            }
+        };
+
+        match self {
+            Self::Inp(_) => unreachable!("inp has no operand to apply"),
+            Self::Add(..) => Some(Interval::new(
+                lhs.min.saturating_add(rhs.min),
+                lhs.max.saturating_add(rhs.max),
+            )),
+            Self::Mul(..) => Some(corners(i64::saturating_mul, lhs, rhs)),
+            Self::Div(..) => {
+                let parts = [rhs.at_most(-1), rhs.at_least(1)];
+                let quotients = parts
+                    .into_iter()
+                    .flatten()
+                    .map(|rhs| corners(i64::saturating_div, lhs, rhs));
+                let hull = Interval::hull(quotients.flat_map(|q| [q.min, q.max]));
+                (hull.min <= hull.max).then_some(hull)
+            }
+            Self::Mod(..) => {
+                let (lhs, rhs) = (lhs.at_least(0)?, rhs.at_least(1)?);
+                if lhs.max < rhs.min {
+                    Some(lhs)
+                } else {
+                    Some(Interval::new(0, lhs.max.min(rhs.max - 1)))
+                }
+            }
+            Self::Eql(..) => Some(compare(true)),
+            Self::Neq(..) => Some(compare(false)),
+            Self::Set(..) => Some(rhs),
+        }
+    }
+}
+
+/// Runs `program` over intervals, feeding `input` for every `inp`. Returns
+/// the register intervals after each instruction, or `None` from the first
+/// instruction that always faults.
+fn interpret_intervals(
+    mut registers: [Interval; 4],
+    program: &[Instruction],
+    input: Interval,
+) -> Vec<Option<[Interval; 4]>> {
+    let mut states = Vec::new();
+    let mut reachable = true;
+
+    for instruction in program {
+        let a = instruction.target().index();
+        let result = match instruction.operand() {
+            _ if !reachable => None,
+            None => Some(input),
+            Some(Operand::Register(b)) => {
+                instruction.apply_interval(registers[a], registers[b.index()])
+            }
+            Some(Operand::Literal(b)) => {
+                instruction.apply_interval(registers[a], Interval::exact(b))
+            }
+        };
+
+        match result {
+            Some(r) => {
+                registers[a] = r;
+                states.push(Some(registers));
+            }
+            None => {
+                reachable = false;
+                states.push(None);
+            }
+        }
+    }
+
+    states
+}
+
+/// Splits `program` at each `inp`. Instructions before the first `inp` are
+/// returned separately.
+fn split_blocks(program: &[Instruction]) -> (Range<usize>, Vec<Range<usize>>) {
+    let starts: Vec<_> = program
+        .iter()
+        .enumerate()
+        .filter(|(_, i)| matches!(i, Instruction::Inp(_)))
+        .map(|(pc, _)| pc)
+        .collect();
+
+    let prologue = 0..starts.first().copied().unwrap_or(program.len());
+    let blocks = starts
+        .iter()
+        .zip(starts.iter().skip(1).chain([&program.len()]))
+        .map(|(&start, &end)| start..end)
+        .collect();
+    (prologue, blocks)
+}
+
+/// Bounds on `z` at the end of each `inp` block, with every input in
+/// `digits`. `None` marks blocks that always fault.
+fn block_bounds(program: &[Instruction], digits: RangeInclusive<i64>) -> Vec<Option<Interval>> {
+    let (_, blocks) = split_blocks(program);
+    let states = interpret_intervals(
+        [Interval::exact(0); 4],
+        program,
+        Interval::new(*digits.start(), *digits.end()),
+    );
+
+    blocks
+        .iter()
+        .map(|block| states[block.end - 1].map(|r| r[Register::Z.index()]))
+        .collect()
+}
+
+/// Depth-first search for the largest or smallest input, one digit per
+/// `inp`, that leaves `z == 0`. Branches are cut once interval analysis shows
+/// the rest of the program can no longer bring `z` back to zero.
+struct Search<'a> {
+    program: &'a [Instruction],
+    blocks: Vec<Range<usize>>,
+    digits: Vec<i64>,
+    /// Values of `z` at the start of each block (and at the end of the
+    /// program) from which zero may still be reachable.
+    reachable: Vec<Interval>,
+    /// Registers live at the start of each block.
+    live: Vec<[bool; 4]>,
+    dead: HashSet<(usize, [i64; 4])>,
+}
+
+impl<'a> Search<'a> {
+    fn new(program: &'a [Instruction], digits: RangeInclusive<i64>, largest: bool) -> Self {
+        let (_, blocks) = split_blocks(program);
+        let input = Interval::new(*digits.start(), *digits.end());
+
+        let mut reachable = Vec::new();
+        let mut live = Vec::new();
+        for start in blocks.iter().map(|b| b.start).chain([program.len()]) {
+            let suffix = &program[start..];
+            // Whether zero may be reachable with `z` anywhere in `z_in`.
+            let may_reach = |z_in: Interval| {
+                let mut registers = [Interval::FULL; 4];
+                registers[Register::Z.index()] = z_in;
+                match interpret_intervals(registers, suffix, input).last() {
+                    Some(Some(r)) => r[Register::Z.index()].contains(0),
+                    Some(None) => false,
+                    None => z_in.contains(0),
+                }
+            };
+            // Binary search for the smallest `t` such that no `z >= t`
+            // reaches zero, and likewise for `z <= -t`.
+            let cutoff = |side: fn(i64) -> Interval| {
+                let (mut lo, mut hi) = (0, i64::MAX);
+                if may_reach(side(hi)) {
+                    return None;
+                }
+                while lo < hi {
+                    let mid = lo + (hi - lo) / 2;
+                    if may_reach(side(mid)) {
+                        lo = mid + 1;
+                    } else {
+                        hi = mid;
+                    }
+                }
+                Some(lo)
+            };
+            let max = cutoff(|t| Interval::new(t, i64::MAX)).map_or(i64::MAX, |t| t - 1);
+            let min = cutoff(|t| Interval::new(i64::MIN, -t)).map_or(i64::MIN, |t| 1 - t);
+            reachable.push(Interval::new(min, max));
+
+            let mut live_in = [false; 4];
+            live_in[Register::Z.index()] = true;
+            for instruction in suffix.iter().rev() {
+                update_liveness(*instruction, &mut live_in);
+            }
+            live.push(live_in);
+        }
+
+        let mut digits: Vec<_> = digits.collect();
+        if largest {
+            digits.reverse();
+        }
+
+        Self {
+            program,
+            blocks,
+            digits,
+            reachable,
+            live,
+            dead: HashSet::new(),
+        }
+    }
+
+    fn run(&mut self) -> Option<Vec<i64>> {
+        let (prologue, _) = split_blocks(self.program);
+        let registers = execute_from([0; 4], &self.program[prologue], [].into_iter()).ok()?;
+        let mut found = self.search(0, registers)?;
+        found.reverse();
+        Some(found)
+    }
+
+    /// Returns the digits for blocks `block..`, in reverse order.
+    fn search(&mut self, block: usize, mut registers: [i64; 4]) -> Option<Vec<i64>> {
+        if !self.reachable[block].contains(registers[Register::Z.index()]) {
+            return None;
+        }
+        if block == self.blocks.len() {
+            return Some(Vec::new());
+        }
+
+        for (r, live) in registers.iter_mut().zip(self.live[block]) {
+            if !live {
+                *r = 0;
+            }
+        }
+        if self.dead.contains(&(block, registers)) {
+            return None;
+        }
+
+        let instructions = &self.program[self.blocks[block].clone()];
+        for i in 0..self.digits.len() {
+            let digit = self.digits[i];
+            let next = match execute_from(registers, instructions, [digit].into_iter()) {
+                Ok(next) => next,
+                Err(_) => continue,
+            };
+            if let Some(mut found) = self.search(block + 1, next) {
+                found.push(digit);
+                return Some(found);
+            }
+        }
+
+        self.dead.insert((block, registers));
+        None
+    }
+}
+
+/// Prints the bounds on `z` after each block of the ALU program at `path`,
+/// with inputs in `1..=9`.
+pub fn bounds_command(path: &str) -> Result<(), String> {
+    let program = read_program(path)?;
+
+    for (i, bounds) in block_bounds(&program, 1..=9).into_iter().enumerate() {
+        match bounds {
+            Some(z) => println!("{:>3}: {}..={}", i + 1, z.min, z.max),
+            None => println!("{:>3}: always faults", i + 1),
+        }
+    }
+    Ok(())
+}
+
+fn read_program(path: &str) -> Result<Vec<Instruction>, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("Unable to read program: {}", e))?;
+    parse_program(&content).map_err(|e| format!("Unable to parse program: {}", e))
+}
+
+#[allow(unused_macros)]
 macro_rules! build_alu {
     ( $name:ident, $w:ident, $x:ident, $y:ident, $z:ident, { $($rest:tt)* } ) => {
+        #[allow(unused_assignments)]
         fn $name(mut input: impl Iterator<Item=i64>) -> (i64, i64, i64, i64) {
             let mut $w = 0;
             let mut $x = 0;
@@ -33,267 +828,40 @@ macro_rules! build_alu {
     ( @inner $input:ident, $w:ident, $x:ident, $y:ident, $z:ident, { } ) => { };
 }
 
-include!("../input/day24");
-/*
-//fn alu(_: impl Iterator<Item=i32>) -> (i32, i32, i32, i32) {
-//    todo!()
-//}
-build_alu!(alu, w, x, y, z, {
-// 1
-inp w
-mul x 0
-add x z
-mod x 26
-div z 1
-add x 10
-eql x w
-eql x 0
-mul y 0
-add y 25
-mul y x
-add y 1
-mul z y
-mul y 0
-add y w
-add y 12
-mul y x
-add z y
-// 2
-inp w
-mul x 0
-add x z
-mod x 26
-div z 1
-add x 12
-eql x w
-eql x 0
-mul y 0
-add y 25
-mul y x
-add y 1
-mul z y
-mul y 0
-add y w
-add y 7
-mul y x
-add z y
-// 3
-inp w
-mul x 0
-add x z
-mod x 26
-div z 1
-add x 10
-eql x w
-eql x 0
-mul y 0
-add y 25
-mul y x
-add y 1
-mul z y
-mul y 0
-add y w
-add y 8
-mul y x
-add z y
-// 4
-inp w
-mul x 0
-add x z
-mod x 26
-div z 1
-add x 12
-eql x w
-eql x 0
-mul y 0
-add y 25
-mul y x
-add y 1
-mul z y
-mul y 0
-add y w
-add y 8
-mul y x
-add z y
-// 5
-inp w
-mul x 0
-add x z
-mod x 26
-div z 1
-add x 11
-eql x w
-eql x 0
-mul y 0
-add y 25
-mul y x
-add y 1
-mul z y
-mul y 0
-add y w
-add y 15
-mul y x
-add z y
-// 6
-inp w
-mul x 0
-add x z
-mod x 26
-div z 26
-add x -16
-eql x w
-eql x 0
-mul y 0
-add y 25
-mul y x
-add y 1
-mul z y
-mul y 0
-add y w
-add y 12
-mul y x
-add z y
-// 7
-inp w
-mul x 0
-add x z
-mod x 26
-div z 1
-add x 10
-eql x w
-eql x 0
-mul y 0
-add y 25
-mul y x
-add y 1
-mul z y
-mul y 0
-add y w
-add y 8
-mul y x
-add z y
-// 8
-inp w
-mul x 0
-add x z
-mod x 26
-div z 26
-add x -11
-eql x w
-eql x 0
-mul y 0
-add y 25
-mul y x
-add y 1
-mul z y
-mul y 0
-add y w
-add y 13
-mul y x
-add z y
-/*
-// 9
-inp w
-mul x 0
-add x z
-mod x 26
-div z 26
-add x -13
-eql x w
-eql x 0
-mul y 0
-add y 25
-mul y x
-add y 1
-mul z y
-mul y 0
-add y w
-add y 3
-mul y x
-add z y
-// 10
-inp w
-mul x 0
-add x z
-mod x 26
-div z 1
-add x 13
-eql x w
-eql x 0
-mul y 0
-add y 25
-mul y x
-add y 1
-mul z y
-mul y 0
-add y w
-add y 13
-mul y x
-add z y
-// 11
-inp w
-mul x 0
-add x z
-mod x 26
-div z 26
-add x -8
-eql x w
-eql x 0
-mul y 0
-add y 25
-mul y x
-add y 1
-mul z y
-mul y 0
-add y w
-add y 3
-mul y x
-add z y
-// 12
-inp w
-mul x 0
-add x z
-mod x 26
-div z 26
-add x -1
-eql x w
-eql x 0
-mul y 0
-add y 25
-mul y x
-add y 1
-mul z y
-mul y 0
-add y w
-add y 9
-mul y x
-add z y
-// 13
-inp w
-mul x 0
-add x z
-mod x 26
-div z 26
-add x -4
-eql x w
-eql x 0
-mul y 0
-add y 25
-mul y x
-add y 1
-mul z y
-mul y 0
-add y w
-add y 4
-mul y x
-add z y
-// 14
+// With the `compiled-alu` feature the puzzle program is compiled into `alu`
+// instead of being interpreted. `input/day24.rs` must contain the puzzle text
+// wrapped as `build_alu!(alu, w, x, y, z, { ... });`.
+#[cfg(feature = "compiled-alu")]
+include!("../input/day24.rs");
+
+#[cfg(not(feature = "compiled-alu"))]
+fn is_valid(program: &[Instruction], digits: &[i64]) -> bool {
+    matches!(execute(program, digits.iter().copied()), Ok((_, _, _, 0)))
+}
+
+#[cfg(feature = "compiled-alu")]
+fn is_valid(_: &[Instruction], digits: &[i64]) -> bool {
+    let (_, _, _, z) = alu(digits.iter().copied());
+    z == 0
+}
+
+/// One `inp w` block of the MONAD program. Blocks with `div == 1` push
+/// `digit + add_y` onto a base-26 stack held in `z`; blocks with `div == 26`
+/// pop it again, but only if `digit == popped + add_x`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Block {
+    div: i64,
+    add_x: i64,
+    add_y: i64,
+}
+
+const BLOCK_TEMPLATE: &str = "\
 inp w
 mul x 0
 add x z
 mod x 26
-div z 26
-add x -14
+div z 0
+add x 0
 eql x w
 eql x 0
 mul y 0
@@ -303,299 +871,139 @@ add y 1
 mul z y
 mul y 0
 add y w
-add y 13
+add y 0
 mul y x
-add z y
-*/
-});
-*/
+add z y";
 
-fn simplified(mut input: impl Iterator<Item=i32>) -> (i32, i32, i32, i32) {
-    let mut w = 0i32;
-    let mut x = 0i32;
-    let mut y = 0i32;
-    let mut z = 0i32;
-
-    // 1
-    w = input.next().unwrap();
-    z = w + 12;
-
-    // 2
-    w = input.next().unwrap();
-    z = (z * 26) + w + 7;
-
-    // 3
-    w = input.next().unwrap();
-    z = (z * 26) + w + 8;
+fn analyze(program: &[Instruction]) -> Result<Vec<Block>, String> {
+    fn split_literal(instruction: Instruction) -> Option<(Instruction, i64)> {
+        match instruction {
+            Instruction::Div(a, Operand::Literal(v)) => {
+                Some((Instruction::Div(a, Operand::Literal(0)), v))
+            }
+            Instruction::Add(a, Operand::Literal(v)) => {
+                Some((Instruction::Add(a, Operand::Literal(0)), v))
+            }
+            _ => None,
+        }
+    }
 
-    // 4
-    w = input.next().unwrap();
-    z = (z * 26) + w + 8;
+    let template = parse_program(BLOCK_TEMPLATE).unwrap();
+    if program.is_empty() || !program.len().is_multiple_of(template.len()) {
+        return Err(format!(
+            "program length {} is not a multiple of the {} instruction block",
+            program.len(),
+            template.len()
+        ));
+    }
 
-    // 5
-    w = input.next().unwrap();
-    z = (z * 26) + w + 15;
+    let mut blocks = Vec::new();
+    for (n, chunk) in program.chunks(template.len()).enumerate() {
+        let mut params = Vec::new();
+        for (i, (actual, expected)) in chunk.iter().zip(template.iter()).enumerate() {
+            let matches = match (split_literal(*actual), split_literal(*expected)) {
+                (Some((a, v)), Some((e, 0))) if matches!(i, 4 | 5 | 15) => {
+                    params.push(v);
+                    a == e
+                }
+                _ => actual == expected,
+            };
+            if !matches {
+                return Err(format!(
+                    "instruction {} is {:?}, expected {:?}",
+                    n * template.len() + i,
+                    actual,
+                    expected
+                ));
+            }
+        }
 
-    // 6
-    w = input.next().unwrap();
-    x = z % 26 - 16;
-    x = (x != w) as i32;
-    z = (z / 26) * ((25 * x) + 1) + (w + 12) * x;
+        let block = Block {
+            div: params[0],
+            add_x: params[1],
+            add_y: params[2],
+        };
+        match block.div {
+            1 if block.add_x > 9 => (),
+            26 => (),
+            _ => {
+                return Err(format!(
+                    "block {} has unsupported parameters {:?}",
+                    n, block
+                ))
+            }
+        }
+        blocks.push(block);
+    }
 
-    // 7
-    w = input.next().unwrap();
-    z = (z * 26) + w + 8;
+    Ok(blocks)
+}
 
-    // 8
-    w = input.next().unwrap();
-    x = z % 26 - 11;
-    x = (x != w) as i32;
-    z /= 26;
-    y = 25 * x + 1;
-    z *= y;
-    y = (w + 13) * x;
-    z += y;
-
-    /*
-    // 9
-    w = input.next().unwrap();
-    x = 0;
-    x += z;
-    x %= 26;
-    z /= 26;
-    x += -13;
-    x = (x == w) as i32;
-    x = (x == 0) as i32;
-    y = 0;
-    y += 25;
-    y *= x;
-    y += 1;
-    z *= y;
-    y = 0;
-    y += w;
-    y += 3;
-    y *= x;
-    z += y;
-
-    // 10
-    w = input.next().unwrap();
-    x = 0;
-    x += z;
-    x %= 26;
-    x += 13;
-    x = (x == w) as i32;
-    x = (x == 0) as i32;
-    y = 0;
-    y += 25;
-    y *= x;
-    y += 1;
-    z *= y;
-    y = 0;
-    y += w;
-    y += 13;
-    y *= x;
-    z += y;
-
-    // 11
-    w = input.next().unwrap();
-    x = 0;
-    x += z;
-    x %= 26;
-    z /= 26;
-    x += -8;
-    x = (x == w) as i32;
-    x = (x == 0) as i32;
-    y = 0;
-    y += 25;
-    y *= x;
-    y += 1;
-    z *= y;
-    y = 0;
-    y += w;
-    y += 3;
-    y *= x;
-    z += y;
-
-    // 12
-    w = input.next().unwrap();
-    x = 0;
-    x += z;
-    x %= 26;
-    z /= 26;
-    x += -1;
-    x = (x == w) as i32;
-    x = (x == 0) as i32;
-    y = 0;
-    y += 25;
-    y *= x;
-    y += 1;
-    z *= y;
-    y = 0;
-    y += w;
-    y += 9;
-    y *= x;
-    z += y;
-
-    // 13
-    w = input.next().unwrap();
-    x = 0;
-    x += z;
-    x %= 26;
-    z /= 26;
-    x += -4;
-    x = (x == w) as i32;
-    x = (x == 0) as i32;
-    y = 0;
-    y += 25;
-    y *= x;
-    y += 1;
-    z *= y;
-    y = 0;
-    y += w;
-    y += 4;
-    y *= x;
-    z += y;
-
-    // 14
-    w = input.next().unwrap();
-    x = 0;
-    x += z;
-    x %= 26;
-    z /= 26;
-    x += -14;
-    x = (x == w) as i32;
-    x = (x == 0) as i32;
-    y = 0;
-    y += 25;
-    y *= x;
-    y += 1;
-    z *= y;
-    y = 0;
-    y += w;
-    y += 13;
-    y *= x;
-    z += y;
-    */
+/// Pairs up pushing and popping blocks. Each constraint `(i, j, offset)`
+/// requires `digits[j] == digits[i] + offset`.
+fn constraints(blocks: &[Block]) -> Result<Vec<(usize, usize, i64)>, String> {
+    let mut stack = Vec::new();
+    let mut constraints = Vec::new();
+    for (j, block) in blocks.iter().enumerate() {
+        if block.div == 1 {
+            stack.push((j, block.add_y));
+        } else {
+            let (i, add_y) = stack
+                .pop()
+                .ok_or_else(|| format!("block {} pops from an empty stack", j))?;
+            constraints.push((i, j, add_y + block.add_x));
+        }
+    }
 
-    (w, x, y, z)
+    match stack.pop() {
+        Some((i, _)) => Err(format!("block {} is never popped", i)),
+        None => Ok(constraints),
+    }
 }
 
-const BASE: i64 = 9;
-const DIGITS: u32 = 14;
-const CHUNK_SIZE: i64 = 10_000_000;
+/// Builds a model number by letting `choose` pick the earlier digit of each
+/// constrained pair from the range of digits that keeps both in `1..=9`.
+fn model_number(
+    blocks: &[Block],
+    choose: impl Fn(RangeInclusive<i64>) -> i64,
+) -> Result<Vec<i64>, String> {
+    let mut digits = vec![0; blocks.len()];
+    for (i, j, offset) in constraints(blocks)? {
+        let range = 1.max(1 - offset)..=9.min(9 - offset);
+        if range.is_empty() {
+            return Err(format!(
+                "digits {} and {} cannot differ by {}",
+                i, j, offset
+            ));
+        }
+        digits[i] = choose(range);
+        digits[j] = digits[i] + offset;
+    }
 
-fn problem1() -> i64 {
-    let mut max = BASE.pow(DIGITS) - 1;
+    Ok(digits)
+}
 
-    let blah: Vec<_> = (0..=max).rev().step_by(CHUNK_SIZE as usize).take(10_000).collect();
-    let v = blah.into_par_iter().find(|n| klonk(*n).is_some());
-    println!("{:?}", v);
-    let mut start = v.unwrap();
+/// Solves recognized MONAD programs directly, and falls back to searching
+/// for anything else.
+fn solve(program: &[Instruction], largest: bool) -> i64 {
+    let digits = match analyze(program) {
+        Ok(blocks) => model_number(&blocks, |r| if largest { *r.end() } else { *r.start() })
+            .unwrap_or_else(|e| panic!("No valid model number: {}", e)),
+        Err(_) => Search::new(program, 1..=9, largest)
+            .run()
+            .expect("No valid model number"),
+    };
+    assert!(is_valid(program, &digits));
 
+    digits.into_iter().fold(0, |n, d| 10 * n + d)
+}
 
-    loop {
-        let mut digits = Vec::new();
-        let mut x = start;
-        for _ in 0..DIGITS {
-            digits.push(x % BASE + 1);
-            x /= BASE;
-        }
-        //digits.reverse();
-        //println!("{:?} {}", digits, start);
-        if start % 1_000_000 == 0 {
-            println!("{}", start);
-        }
-
-        let (_, _, _, z) = alu(digits.into_iter());
-        if z == 0 {
-            return start;
-        }
-
-        start -= 1;
-    }
-
-    /*
-    let tests = [
-        "31288293787554",
-        "21656894783444",
-        "17612796679537",
-        "82219835129373",
-        "47885135986739",
-        "79185978272988",
-        "19362153428195",
-        "62149298757822",
-        "71361619821749",
-        "72316589515119",
-        "23364676596428",
-        "83188337246959",
-        "93565787732619",
-        "88716791737243",
-        "26382339382993",
-        "45712824873488",
-        "83671886197764",
-        "91738465523587",
-        "66553838364458",
-        "73155475435269",
-        "47328537858657",
-        "44545371799452",
-        "57687919148336",
-        "61696392885269",
-        "22973196738751",
-    ];
+fn problem1(program: &[Instruction]) -> i64 {
+    solve(program, true)
+}
 
-    for t in tests.iter() {
-        let input: Vec<_> = t.chars().map(|c| c.to_digit(10).unwrap() as i32).collect();
-        let a = alu(input.clone().into_iter());
-        let b = simplified(input.into_iter());
-        if a != b {
-            println!("[{}]: {:?} != {:?}", t, a, b);
-            break;
-        }
-    }
-    //'outer: for i in 1..=9 {
-    //    for j in 1..=9 {
-    //        for k in 1..=9 {
-    //            let a = alu([i, j, k].into_iter());
-    //            let b = simplified([i, j, k].into_iter());
-    //            if a != b {
-    //                println!("[{}, {}, {}]: {:?} != {:?}", i, j, k, a, b);
-    //                break 'outer;
-    //            }
-    //        }
-    //    }
-    //}
-//    //let x = 99999999999999i64;
-//    let x = 88888888888888i64;
-//    let raw_input = x.to_string();
-//    let input = raw_input.chars().map(|c| c.to_digit(10).unwrap() as i32);
-//    let (w, x, y, z) = alu(input);
-    //println!("w = {}", w);
-    //println!("x = {}", x);
-    //println!("y = {}", y);
-    //println!("z = {}", z);
-    */
-    todo!()
-}
-
-fn klonk(mut value: i64) -> Option<i64> {
-    println!("{}", value);
-
-    for _ in 0..CHUNK_SIZE {
-        let mut digits = Vec::new();
-        let mut x = value;
-        for _ in 0..DIGITS {
-            digits.push(x % BASE + 1);
-            x /= BASE;
-        }
-
-        let (_, _, _, z) = alu(digits.into_iter());
-        if z == 0 {
-            return Some(value);
-        }
-        value -= 1;
-    }
-    
-    None
+fn problem2(program: &[Instruction]) -> i64 {
+    solve(program, false)
 }
 
 #[cfg(test)]
@@ -604,6 +1012,48 @@ mod tests {
 
     const SAMPLE: &str = "13579246899999";
 
+    const SAMPLE_PROGRAM: &str = "\
+inp w
+add z w
+mod z 2
+div w 2
+add y w
+mod y 2
+div w 2
+add x w
+mod x 2
+div w 2
+mod w 2";
+
+    const MONAD_BLOCKS: [(i64, i64, i64); 14] = [
+        (1, 10, 12),
+        (1, 12, 7),
+        (1, 10, 8),
+        (1, 12, 8),
+        (1, 11, 15),
+        (26, -16, 12),
+        (1, 10, 8),
+        (26, -11, 13),
+        (26, -13, 3),
+        (1, 13, 13),
+        (26, -8, 3),
+        (26, -1, 9),
+        (26, -4, 4),
+        (26, -14, 13),
+    ];
+
+    fn monad() -> Vec<Instruction> {
+        let mut raw = String::new();
+        for (div, add_x, add_y) in MONAD_BLOCKS {
+            raw += &BLOCK_TEMPLATE
+                .replace("div z 0", &format!("div z {}", div))
+                .replace("add x 0", &format!("add x {}", add_x))
+                .replace("add y 0", &format!("add y {}", add_y));
+            raw += "\n";
+        }
+        parse_program(&raw).unwrap()
+    }
+
     build_alu!(sample_alu, w, x, y, z, {
         inp w
         add z w
@@ -620,8 +1070,205 @@ mod tests {
 
     #[test]
     fn example1() {
-        let input = SAMPLE.chars().map(|c| c.to_digit(10).unwrap() as i32);
+        let input = SAMPLE.chars().map(|c| c.to_digit(10).unwrap() as i64);
         let output = sample_alu(input);
         assert_eq!(output, (0, 0, 0, 1));
     }
+
+    #[test]
+    fn interpreter() {
+        let program = parse_program(SAMPLE_PROGRAM).unwrap();
+        for n in 0..16 {
+            let expected = sample_alu([n].into_iter());
+            assert_eq!(execute(&program, [n].into_iter()), Ok(expected));
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        let err = parse_program("inp w\nadd x 1\nsub x 2").unwrap_err();
+        assert_eq!(err.line, 3);
+        assert_eq!(parse_program("\nmul q 2").unwrap_err().line, 2);
+        assert_eq!(parse_program("inp").unwrap_err().line, 1);
+        assert_eq!(parse_program("add x").unwrap_err().line, 1);
+        assert_eq!(parse_program("eql x 1 2").unwrap_err().line, 1);
+    }
+
+    #[test]
+    fn runtime_errors() {
+        fn run(src: &str, input: &[i64]) -> Result<(i64, i64, i64, i64), AluError> {
+            execute(&parse_program(src).unwrap(), input.iter().copied())
+        }
+
+        assert_eq!(
+            run("inp w\ndiv x w", &[0]),
+            Err(AluError::DivisionByZero(1))
+        );
+        assert_eq!(run("mod x 0", &[]), Err(AluError::ModuloByZero(0)));
+        assert_eq!(
+            run("inp w\nmod w 3", &[-1]),
+            Err(AluError::NegativeModulo(1))
+        );
+        assert_eq!(
+            run("add x 5\nmod x -3", &[]),
+            Err(AluError::NegativeModulo(1))
+        );
+        assert_eq!(run("inp w\ninp x", &[1]), Err(AluError::InputExhausted(1)));
+    }
+
+    #[test]
+    fn analysis() {
+        let blocks = analyze(&monad()).unwrap();
+        assert_eq!(blocks.len(), 14);
+        assert_eq!(
+            blocks[5],
+            Block {
+                div: 26,
+                add_x: -16,
+                add_y: 12
+            }
+        );
+        assert!(analyze(&parse_program(SAMPLE_PROGRAM).unwrap()).is_err());
+    }
+
+    #[test]
+    fn largest_model_number() {
+        assert_eq!(problem1(&monad()), 96299896449997);
+    }
+
+    #[test]
+    fn smallest_model_number() {
+        assert_eq!(problem2(&monad()), 31162141116841);
+    }
+
+    #[test]
+    fn instruction_display() {
+        for line in ["inp w", "add x -3", "mul y z", "neq x w", "set z 0"] {
+            assert_eq!(line.parse::<Instruction>().unwrap().to_string(), line);
+        }
+    }
+
+    #[test]
+    fn optimize_monad() {
+        let program = monad();
+        let optimized = optimize(&program, &[Register::Z]);
+        assert!(optimized.len() < program.len());
+        assert!(optimized.contains(&Instruction::Neq(
+            Register::X,
+            Operand::Register(Register::W)
+        )));
+        assert!(!optimized.contains(&Instruction::Mul(Register::X, Operand::Literal(0))));
+        assert_eq!(
+            check_equivalence(&program, &optimized, &[Register::Z], 1..=9, 1000, 24),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn optimize_passes() {
+        let program =
+            parse_program("inp w\nmul x 0\nadd x w\nadd y 3\nmul y 2\neql x w\neql x 0").unwrap();
+        let optimized = optimize(&program, &[Register::X, Register::Y]);
+        let expected = parse_program("inp w\nset x w\nset y 6\nneq x w").unwrap();
+        assert_eq!(optimized, expected);
+        let optimized = optimize(&program, &[Register::Y]);
+        let expected = parse_program("inp w\nset y 6").unwrap();
+        assert_eq!(optimized, expected);
+
+        let program = parse_program("inp z\nset z 4\nadd x z").unwrap();
+        let expected = parse_program("inp z\nset z 4\nset x 4").unwrap();
+        assert_eq!(optimize(&program, &[Register::X, Register::Z]), expected);
+    }
+
+    #[test]
+    fn equivalence_counterexample() {
+        let program = monad();
+        let mut broken = optimize(&program, &[Register::Z]);
+        let last = broken.len() - 3;
+        broken[last] = Instruction::Add(Register::Y, Operand::Literal(14));
+        assert!(check_equivalence(&program, &broken, &[Register::Z], 1..=9, 1000, 24).is_err());
+    }
+
+    #[test]
+    fn emit() {
+        let program = parse_program("inp w\nneq x w\nset y 25\nmod z 26").unwrap();
+        let expected = "\
+#[allow(unused_assignments, unused_mut)]
+fn alu(mut input: impl Iterator<Item = i64>) -> (i64, i64, i64, i64) {
+    let mut w = 0i64;
+    let mut x = 0i64;
+    let mut y = 0i64;
+    let mut z = 0i64;
+
+    // 1
+    w = input.next().unwrap();
+    x = (x != w) as i64;
+    y = 25;
+    z %= 26;
+
+    (w, x, y, z)
+}
+";
+        assert_eq!(emit_rust("alu", &program), expected);
+    }
+
+    #[test]
+    fn interval_arithmetic() {
+        let add = Instruction::Add(Register::X, Operand::Literal(0));
+        let div = Instruction::Div(Register::X, Operand::Literal(0));
+        let modulo = Instruction::Mod(Register::X, Operand::Literal(0));
+        let eql = Instruction::Eql(Register::X, Operand::Literal(0));
+
+        let (a, b) = (Interval::new(-3, 10), Interval::new(1, 9));
+        assert_eq!(add.apply_interval(a, b), Some(Interval::new(-2, 19)));
+        assert_eq!(
+            div.apply_interval(a, Interval::new(-2, 2)),
+            Some(Interval::new(-10, 10))
+        );
+        assert_eq!(div.apply_interval(a, Interval::exact(0)), None);
+        assert_eq!(
+            modulo.apply_interval(a, Interval::exact(26)),
+            Some(Interval::new(0, 10))
+        );
+        assert_eq!(
+            modulo.apply_interval(a, Interval::exact(4)),
+            Some(Interval::new(0, 3))
+        );
+        assert_eq!(modulo.apply_interval(Interval::new(-5, -1), b), None);
+        assert_eq!(
+            eql.apply_interval(a, Interval::exact(11)),
+            Some(Interval::exact(0))
+        );
+        assert_eq!(eql.apply_interval(a, b), Some(Interval::new(0, 1)));
+    }
+
+    #[test]
+    fn monad_block_bounds() {
+        let bounds = block_bounds(&monad(), 1..=9);
+        assert_eq!(bounds.len(), 14);
+        assert_eq!(bounds[0], Some(Interval::new(13, 21)));
+        assert_eq!(bounds[1], Some(Interval::new(13 * 26 + 8, 21 * 26 + 16)));
+    }
+
+    #[test]
+    fn search_monad() {
+        let program = monad();
+        let largest = Search::new(&program, 1..=9, true).run().unwrap();
+        let smallest = Search::new(&program, 1..=9, false).run().unwrap();
+        assert_eq!(largest, [9, 6, 2, 9, 9, 8, 9, 6, 4, 4, 9, 9, 9, 7]);
+        assert_eq!(smallest, [3, 1, 1, 6, 2, 1, 4, 1, 1, 1, 6, 8, 4, 1]);
+    }
+
+    #[test]
+    fn search_other_program() {
+        // z == 0 requires the digits to add up to nine if the first is odd,
+        // and to ten otherwise.
+        let program = parse_program(
+            "inp w\nmul w 2\nadd z w\nmod w 4\nadd y w\ninp w\nmul w 2\nadd z w\nadd z y\nadd z -20",
+        )
+        .unwrap();
+        assert!(analyze(&program).is_err());
+        assert_eq!(solve(&program, true), 82);
+        assert_eq!(solve(&program, false), 18);
+    }
 }
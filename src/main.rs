@@ -1,5 +1,6 @@
 #![recursion_limit = "256"]
 
+mod bigint;
 mod day01;
 mod day02;
 mod day03;
@@ -20,9 +21,77 @@ mod day17;
 mod day20;
 mod day21;
 mod day22;
+mod day24;
 mod day25;
+mod map;
+mod rng;
+
+use std::env;
+use std::process;
 
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if !args.is_empty() {
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        let result = match args[..] {
+            ["day05", "map", path] => day05::map_command(path, None),
+            ["day05", "map", path, image_path] => day05::map_command(path, Some(image_path)),
+            ["day05", "overlaps", path, threshold] => {
+                day05::overlaps_command(path, threshold, None)
+            }
+            ["day05", "overlaps", path, threshold, coverage] => {
+                day05::overlaps_command(path, threshold, Some(coverage))
+            }
+            ["day05", "region", path, corner1, corner2, threshold] => {
+                day05::region_command(path, corner1, corner2, threshold)
+            }
+            ["day05", "stats", path] => day05::stats_command(path, None),
+            ["day05", "stats", path, top] => day05::stats_command(path, Some(top)),
+            ["day06", "count", path, days] => day06::count_command(path, days, None),
+            ["day06", "count", path, days, modulus] => {
+                day06::count_command(path, days, Some(modulus))
+            }
+            ["day06", "history", path, days, ref options @ ..] => {
+                day06::history_command(path, days, options)
+            }
+            ["day06", "when", path, threshold, ref options @ ..] => {
+                day06::when_command(path, threshold, options)
+            }
+            ["day07", "align", path, model, ref options @ ..] => {
+                day07::align_command(path, model, options)
+            }
+            ["day08", "decode", path] => day08::decode_command(path, None),
+            ["day08", "decode", path, glyphs] => day08::decode_command(path, Some(glyphs)),
+            ["day08", "scramble", digits] => day08::scramble_command(digits, None),
+            ["day08", "scramble", digits, seed] => day08::scramble_command(digits, Some(seed)),
+            ["day09", "basins", path] => day09::basins_command(path, None),
+            ["day09", "basins", path, connectivity] => {
+                day09::basins_command(path, Some(connectivity))
+            }
+            ["day16", "compile", expression] => day16::compile_command(expression, None),
+            ["day16", "compile", expression, length_type] => {
+                day16::compile_command(expression, Some(length_type))
+            }
+            ["day16", "disasm", hex] => day16::disasm_command(hex),
+            ["day16", "explain", hex] => day16::explain_command(hex, false),
+            ["day16", "explain", hex, "big"] => day16::explain_command(hex, true),
+            ["day16", "stream", path] => day16::stream_command(path, None),
+            ["day16", "stream", path, encoding] => day16::stream_command(path, Some(encoding)),
+            ["day17", "trace", target, dx, dy] => day17::trace_command(target, (dx, dy), None),
+            ["day17", "trace", target, dx, dy, path] => {
+                day17::trace_command(target, (dx, dy), Some(path))
+            }
+            ["day24", "bounds", path] => day24::bounds_command(path),
+            ["day24", "emit", path] => day24::emit_command(path),
+            _ => Err(format!("Unknown command: {}", args.join(" "))),
+        };
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
     day01::run();
     day02::run();
     day03::run();
@@ -43,5 +112,6 @@ fn main() {
     day20::run();
     day21::run();
     day22::run();
+    day24::run();
     day25::run();
 }
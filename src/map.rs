@@ -0,0 +1,32 @@
+use std::fmt;
+
+/// The most points a map of a puzzle's state may have.
+pub(crate) const MAX_MAP_POINTS: u128 = 1 << 24;
+
+/// The area to map is too large.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct MapTooLarge {
+    pub(crate) width: u128,
+    pub(crate) height: u128,
+}
+
+impl fmt::Display for MapTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "a map of {}x{} points is larger than the {} allowed",
+            self.width, self.height, MAX_MAP_POINTS
+        )
+    }
+}
+
+/// Checks that the area between the corners `min` and `max`, inclusive, can
+/// be mapped, as `(x, y)`.
+pub(crate) fn check_map_size(min: (i64, i64), max: (i64, i64)) -> Result<(), MapTooLarge> {
+    let width = (max.0 as i128 - min.0 as i128 + 1) as u128;
+    let height = (max.1 as i128 - min.1 as i128 + 1) as u128;
+    match width.checked_mul(height) {
+        Some(points) if points <= MAX_MAP_POINTS => Ok(()),
+        _ => Err(MapTooLarge { width, height }),
+    }
+}